@@ -0,0 +1,64 @@
+//! Parses output of `factorio --benchmark`
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Timings of a benchmark run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    pub ticks: u64,
+    pub total_ms: f64,
+    pub avg_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+}
+impl BenchmarkResult {
+    /// Reads the summary lines, e.g.
+    /// `Performed 1000 updates in 4123.456 ms` and
+    /// `avg: 4.123 ms, min: 3.512 ms, max: 8.456 ms`
+    pub fn parse(output: &str) -> Option<Self> {
+        lazy_static! {
+            static ref PERFORMED: Regex =
+                Regex::new(r"Performed (\d+) updates in ([0-9.]+) ms").unwrap();
+            static ref TIMINGS: Regex =
+                Regex::new(r"avg: ([0-9.]+) ms, min: ([0-9.]+) ms, max: ([0-9.]+) ms").unwrap();
+        }
+
+        let performed = PERFORMED.captures(output)?;
+        let timings = TIMINGS.captures(output)?;
+        Some(Self {
+            ticks: performed[1].parse().ok()?,
+            total_ms: performed[2].parse().ok()?,
+            avg_ms: timings[1].parse().ok()?,
+            min_ms: timings[2].parse().ok()?,
+            max_ms: timings[3].parse().ok()?,
+        })
+    }
+
+    /// Updates per second the average tick time allows
+    pub fn ups(&self) -> f64 {
+        1000.0 / self.avg_ms
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_output() {
+        let output = "   0.000 Factorio 0.18.27 (build 52130, linux64, headless)\n\
+                      ...\n  \
+                      Performed 1000 updates in 4123.456 ms\n  \
+                      avg: 4.123 ms, min: 3.512 ms, max: 8.456 ms\n  \
+                      checksum: 1234567890\n";
+        let result = BenchmarkResult::parse(output).unwrap();
+        assert_eq!(result.ticks, 1000);
+        assert_eq!(result.total_ms, 4123.456);
+        assert_eq!(result.avg_ms, 4.123);
+        assert_eq!(result.min_ms, 3.512);
+        assert_eq!(result.max_ms, 8.456);
+
+        assert_eq!(BenchmarkResult::parse("Error: could not load save"), None);
+    }
+}
@@ -0,0 +1,156 @@
+//! Parser for the Factorio `changelog.txt` format used by mods
+
+use std::fmt;
+
+use crate::version::Version;
+
+/// Changes of a single version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangelogEntry {
+    pub version: Version,
+    pub date: Option<String>,
+    /// Category name, e.g. "Bugfixes", and its changes
+    pub categories: Vec<(String, Vec<String>)>,
+}
+impl fmt::Display for ChangelogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Version: {}", self.version)?;
+        if let Some(date) = &self.date {
+            write!(f, " ({})", date)?;
+        }
+        for (category, changes) in &self.categories {
+            write!(f, "\n  {}:", category)?;
+            for change in changes {
+                write!(f, "\n    - {}", change.replace('\n', "\n      "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a changelog, skipping sections that don't follow the format
+pub fn parse(text: &str) -> Vec<ChangelogEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<ChangelogEntry> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if trimmed.chars().all(|c| c == '-') && trimmed.len() > 3 {
+            entries.extend(current.take());
+        } else if let Some(version) = trimmed.strip_prefix("Version:") {
+            entries.extend(current.take());
+            current = Version::try_from_str(version.trim())
+                .ok()
+                .map(|version| ChangelogEntry {
+                    version,
+                    date: None,
+                    categories: Vec::new(),
+                });
+        } else if let Some(entry) = current.as_mut() {
+            if let Some(date) = trimmed.strip_prefix("Date:") {
+                entry.date = Some(date.trim().to_owned());
+            } else if let Some(change) = trimmed.strip_prefix("- ") {
+                if entry.categories.is_empty() {
+                    entry.categories.push(("Changes".to_owned(), Vec::new()));
+                }
+                let (_, changes) = entry.categories.last_mut().unwrap();
+                changes.push(change.to_owned());
+            } else if trimmed.ends_with(':') && !line.starts_with("    ") {
+                let category = trimmed.trim_end_matches(':').to_owned();
+                entry.categories.push((category, Vec::new()));
+            } else if let Some(last) = entry
+                .categories
+                .last_mut()
+                .and_then(|(_, changes)| changes.last_mut())
+            {
+                // Continuation of a multi-line change
+                last.push('\n');
+                last.push_str(trimmed);
+            }
+        }
+    }
+    entries.extend(current);
+
+    entries
+}
+
+/// Entries newer than `from` up to and including `to`, oldest first
+pub fn between(
+    entries: &[ChangelogEntry], from: Option<Version>, to: Option<Version>,
+) -> Vec<&ChangelogEntry> {
+    let mut result: Vec<_> = entries
+        .iter()
+        .filter(|e| from.map_or(true, |from| e.version > from))
+        .filter(|e| to.map_or(true, |to| e.version <= to))
+        .collect();
+    result.sort_by_key(|e| e.version);
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CHANGELOG: &str = "\
+---------------------------------------------------------------------------------------------------
+Version: 1.2.0
+Date: 2020-06-01
+  Features:
+    - Added a thing
+      that spans two lines
+  Bugfixes:
+    - Fixed a crash
+---------------------------------------------------------------------------------------------------
+Version: 1.1.0
+  Changes:
+    - Changed a thing
+---------------------------------------------------------------------------------------------------
+Version: not-a-version
+  Changes:
+    - Ignored
+---------------------------------------------------------------------------------------------------
+Version: 1.0.0
+Date: 2020-01-01
+  Info:
+    - Initial release
+";
+
+    #[test]
+    fn parse_changelog() {
+        let entries = parse(CHANGELOG);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].version, Version::new(1, 2, 0));
+        assert_eq!(entries[0].date.as_deref(), Some("2020-06-01"));
+        assert_eq!(
+            entries[0].categories,
+            vec![
+                (
+                    "Features".to_owned(),
+                    vec!["Added a thing\nthat spans two lines".to_owned()]
+                ),
+                ("Bugfixes".to_owned(), vec!["Fixed a crash".to_owned()]),
+            ]
+        );
+        assert_eq!(entries[1].date, None);
+    }
+
+    #[test]
+    fn entries_between() {
+        let entries = parse(CHANGELOG);
+        let versions: Vec<_> = between(&entries, Some(Version::new(1, 0, 0)), None)
+            .iter()
+            .map(|e| e.version)
+            .collect();
+        assert_eq!(versions, vec![Version::new(1, 1, 0), Version::new(1, 2, 0)]);
+
+        let versions: Vec<_> = between(&entries, None, Some(Version::new(1, 1, 0)))
+            .iter()
+            .map(|e| e.version)
+            .collect();
+        assert_eq!(versions, vec![Version::new(1, 0, 0), Version::new(1, 1, 0)]);
+    }
+}
@@ -1,15 +1,16 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use structopt::StructOpt;
 use strum_macros::EnumString;
 
 use crate::dirs::credentials_file;
-use crate::version::VersionReq;
+use crate::version::{Version, VersionReq};
 
-#[derive(Clone, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
+#[derive(Clone, Default, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct Password {
     pub plaintext: String,
@@ -101,6 +102,45 @@ pub struct MetaConfig {
     /// Automatically apply patches
     #[structopt(long, default_value = "60")]
     pub autoupdate_interval_minutes: u64,
+
+    /// Only allow players on the whitelist to join
+    #[structopt(long)]
+    #[serde(default)]
+    pub use_server_whitelist: bool,
+
+    /// UDP port, a free one is chosen automatically if not given
+    #[structopt(long)]
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// Address to listen on
+    #[structopt(long)]
+    #[serde(default)]
+    pub bind: Option<IpAddr>,
+
+    /// Start this server with `facts up`
+    #[structopt(long)]
+    #[serde(default)]
+    pub autostart: bool,
+
+    /// Update Factorio even if some mods have no release for the new version
+    #[structopt(long)]
+    #[serde(default)]
+    pub allow_incompatible_mods: bool,
+}
+impl Default for MetaConfig {
+    fn default() -> Self {
+        Self {
+            factorio: VersionReq::Stable,
+            autoupdate: AutoUpdate::Enabled,
+            autoupdate_interval_minutes: 60,
+            use_server_whitelist: false,
+            port: None,
+            bind: None,
+            autostart: false,
+            allow_incompatible_mods: false,
+        }
+    }
 }
 impl MetaConfig {
     pub fn apply_update(&mut self, update: MetaConfigUpdate) {
@@ -113,23 +153,56 @@ impl MetaConfig {
         if let Some(v) = update.autoupdate_interval_minutes {
             self.autoupdate_interval_minutes = v;
         }
+        if let Some(v) = update.use_server_whitelist {
+            self.use_server_whitelist = v;
+        }
+        if let Some(v) = update.port {
+            self.port = Some(v);
+        }
+        if let Some(v) = update.bind {
+            self.bind = Some(v);
+        }
+        if let Some(v) = update.autostart {
+            self.autostart = v;
+        }
+        if let Some(v) = update.allow_incompatible_mods {
+            self.allow_incompatible_mods = v;
+        }
     }
 }
 
 /// Configuration that is persisted per-server by facts
-#[derive(Debug, Clone, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 #[structopt(rename_all = "kebab-case")]
 pub struct MetaConfigUpdate {
+    /// Version of Factorio to use
     #[structopt(long)]
     pub factorio: Option<VersionReq>,
+    /// Automatically apply patches
     #[structopt(long)]
     pub autoupdate: Option<AutoUpdate>,
+    /// Minutes between update checks
     #[structopt(long)]
     pub autoupdate_interval_minutes: Option<u64>,
+    /// Only allow players on the whitelist to join
+    #[structopt(long)]
+    pub use_server_whitelist: Option<bool>,
+    /// UDP port
+    #[structopt(long)]
+    pub port: Option<u16>,
+    /// Address to listen on
+    #[structopt(long)]
+    pub bind: Option<IpAddr>,
+    /// Start this server with `facts up`
+    #[structopt(long)]
+    pub autostart: Option<bool>,
+    /// Update Factorio even if some mods have no release for the new version
+    #[structopt(long)]
+    pub allow_incompatible_mods: Option<bool>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 #[structopt(rename_all = "kebab-case")]
 pub struct ImportConfig {
@@ -148,9 +221,13 @@ pub struct ImportConfig {
     /// Import mods from mod-list.json
     #[structopt(long)]
     pub mod_list: Option<PathBuf>,
+
+    /// Import mod settings from mod-settings.dat, or JSON written by `mod-settings export`
+    #[structopt(long)]
+    pub mod_settings: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, StructOpt, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 #[structopt(rename_all = "kebab-case")]
 pub struct CreateConfig {
@@ -169,6 +246,198 @@ pub struct CreateConfig {
     pub meta: MetaConfig,
 }
 
+/// Arguments for creating a server, all optional so that they can override a template
+#[derive(Debug, Clone, PartialEq, Eq, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct CreateArgs {
+    /// Path to map-gen-settings.json
+    #[structopt(long)]
+    pub map_gen_settings: Option<PathBuf>,
+
+    /// Path to map-settings.json
+    #[structopt(long)]
+    pub map_settings: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    pub import: ImportConfig,
+
+    #[structopt(flatten)]
+    pub meta: MetaConfigUpdate,
+}
+impl CreateArgs {
+    /// Overrides values of `base` with the ones given here
+    pub fn apply(self, mut base: CreateConfig) -> CreateConfig {
+        if self.map_gen_settings.is_some() {
+            base.map_gen_settings = self.map_gen_settings;
+        }
+        if self.map_settings.is_some() {
+            base.map_settings = self.map_settings;
+        }
+        if self.import.server_settings.is_some() {
+            base.import.server_settings = self.import.server_settings;
+        }
+        if self.import.server_adminlist.is_some() {
+            base.import.server_adminlist = self.import.server_adminlist;
+        }
+        base.import.add_admin.extend(self.import.add_admin);
+        if self.import.mod_list.is_some() {
+            base.import.mod_list = self.import.mod_list;
+        }
+        if self.import.mod_settings.is_some() {
+            base.import.mod_settings = self.import.mod_settings;
+        }
+        base.meta.apply_update(self.meta);
+        base
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum ModCommand {
+    /// Search the mod portal by name, title and summary
+    Search {
+        query: Vec<String>,
+
+        /// Maximum number of results
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Show details of a mod
+    Info {
+        /// Internal name of the mod
+        name: String,
+
+        /// Show the latest release compatible with this server
+        #[structopt(long)]
+        world: Option<String>,
+    },
+    /// Show changes between two versions of a mod
+    Changelog {
+        /// Internal name of the mod
+        name: String,
+
+        /// Show changes after this version
+        #[structopt(long)]
+        from: Option<Version>,
+
+        /// Show changes up to this version
+        #[structopt(long)]
+        to: Option<Version>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum TemplateCommand {
+    /// Save a template, either from an existing server or from the given settings
+    Save {
+        /// Name of the template
+        template: String,
+
+        /// Server to copy settings, admins and mods from
+        #[structopt(long)]
+        from: Option<String>,
+
+        /// Overwrite existing template
+        #[structopt(long)]
+        force: bool,
+
+        #[structopt(flatten)]
+        args: CreateArgs,
+    },
+    /// List all templates
+    List,
+    /// Delete a template
+    Delete {
+        /// Name of the template
+        template: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum SettingsCommand {
+    /// Display a setting, or all settings if no key is given
+    Get {
+        /// Setting name, nested keys are separated by dots, e.g. visibility.public
+        key: Option<String>,
+    },
+    /// Change a setting
+    Set {
+        /// Setting name, nested keys are separated by dots, e.g. visibility.public
+        key: String,
+
+        /// New value, parsed as JSON if possible and as a string otherwise
+        value: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum ModSettingsCommand {
+    /// Display all mod settings
+    List,
+    /// Display a mod setting
+    Get {
+        /// Setting name
+        setting: String,
+    },
+    /// Change a mod setting
+    Set {
+        /// Setting name
+        setting: String,
+
+        /// New value, parsed as JSON if possible and as a string otherwise
+        value: String,
+
+        /// Scope of new settings: startup, runtime-global or runtime-per-user
+        #[structopt(long)]
+        scope: Option<String>,
+    },
+    /// Replace mod settings with mod-settings.dat or a JSON export
+    Import {
+        /// Path to the file, JSON if it ends with .json
+        path: PathBuf,
+    },
+    /// Write mod settings to a file
+    Export {
+        /// Path to the file, JSON if it ends with .json and mod-settings.dat format otherwise
+        path: PathBuf,
+
+        /// Allow overwriting target file
+        #[structopt(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum PlayerListCommand {
+    /// Add players to the list
+    Add { players: Vec<String> },
+    /// Remove players from the list
+    Remove { players: Vec<String> },
+    /// Display players on the list
+    List,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum BanListCommand {
+    /// Ban a player
+    Add {
+        player: String,
+
+        /// Reason shown to the player
+        #[structopt(long)]
+        reason: Option<String>,
+    },
+    /// Unban players
+    Remove { players: Vec<String> },
+    /// Display banned players
+    List,
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(author, about)]
 #[structopt(rename_all = "kebab-case")]
@@ -179,8 +448,47 @@ pub enum Args {
         /// Name for the server
         name: String,
 
+        /// Use settings from a template, other arguments override them
+        #[structopt(long)]
+        template: Option<String>,
+
+        /// Render a map preview and ask for confirmation before generating the world
+        #[structopt(long, conflicts_with = "scenario")]
+        preview: bool,
+
+        /// Map generation seed, overrides the one in map-gen-settings
+        #[structopt(long, conflicts_with = "scenario")]
+        seed: Option<u32>,
+
+        /// Start from a scenario instead of generating a map.
+        /// Either a scenario directory, mod-name/scenario-name or a scenario included in Factorio
+        #[structopt(long)]
+        scenario: Option<String>,
+
         #[structopt(flatten)]
-        config: CreateConfig,
+        args: CreateArgs,
+    },
+    /// Render a map preview PNG for creation settings
+    Preview {
+        /// Output image
+        #[structopt(short, long, default_value = "preview.png")]
+        output: PathBuf,
+
+        /// Map generation seed, overrides the one in map-gen-settings
+        #[structopt(long)]
+        seed: Option<u32>,
+
+        /// Use settings from a template, other arguments override them
+        #[structopt(long)]
+        template: Option<String>,
+
+        #[structopt(flatten)]
+        args: CreateArgs,
+    },
+    /// Manage server templates
+    Template {
+        #[structopt(subcommand)]
+        command: TemplateCommand,
     },
     /// Import existing world to facts
     Import {
@@ -190,11 +498,44 @@ pub enum Args {
         /// World file
         path: PathBuf,
 
+        /// Import a bundle written by `export --bundle`, using its server config and mods
+        #[structopt(long)]
+        bundle: bool,
+
+        /// Install mods used by the save without asking
+        #[structopt(long, conflicts_with = "bundle")]
+        add_save_mods: bool,
+
         #[structopt(flatten)]
         config: ImportConfig,
 
+        /// Defaults of `create` for saves, overrides for bundles
         #[structopt(flatten)]
-        meta: MetaConfig,
+        meta: MetaConfigUpdate,
+    },
+    /// Copy a server into a new one
+    Clone {
+        /// Name of the server to copy
+        source: String,
+
+        /// Name for the new server
+        name: String,
+
+        /// Start from an autosave instead of the main save, e.g. _autosave1
+        #[structopt(long)]
+        at_snapshot: Option<String>,
+    },
+    /// Rename a server
+    Rename {
+        /// Current name of the server
+        name: String,
+
+        /// New name for the server
+        new_name: String,
+
+        /// Manifest files referencing the server, renamed there as well
+        #[structopt(long, parse(from_os_str))]
+        manifest: Vec<PathBuf>,
     },
     /// Export world to a zip file
     Export {
@@ -204,6 +545,14 @@ pub enum Args {
         /// World file
         path: PathBuf,
 
+        /// Export a bundle with the save, settings files and exact mod versions
+        #[structopt(long)]
+        bundle: bool,
+
+        /// Include mod zips in the bundle instead of downloading them on import
+        #[structopt(long, requires = "bundle")]
+        include_mods: bool,
+
         /// Allow overwriting target file
         #[structopt(long)]
         force: bool,
@@ -219,6 +568,46 @@ pub enum Args {
         #[structopt(flatten)]
         meta: MetaConfigUpdate,
     },
+    /// View or change server-settings.json
+    Settings {
+        /// Name of the server
+        name: String,
+
+        #[structopt(subcommand)]
+        command: SettingsCommand,
+    },
+    /// View or change mod-settings.dat
+    ModSettings {
+        /// Name of the server
+        name: String,
+
+        #[structopt(subcommand)]
+        command: ModSettingsCommand,
+    },
+    /// Manage server admins
+    Admins {
+        /// Name of the server
+        name: String,
+
+        #[structopt(subcommand)]
+        command: PlayerListCommand,
+    },
+    /// Manage server whitelist, enable it with `edit --use-server-whitelist true`
+    Whitelist {
+        /// Name of the server
+        name: String,
+
+        #[structopt(subcommand)]
+        command: PlayerListCommand,
+    },
+    /// Manage banned players
+    Bans {
+        /// Name of the server
+        name: String,
+
+        #[structopt(subcommand)]
+        command: BanListCommand,
+    },
     /// Log in to the mod portal, optionally save credentials
     Login {
         #[structopt(flatten)]
@@ -235,6 +624,14 @@ pub enum Args {
         name: String,
 
         mods: Vec<String>,
+
+        /// Mod zip that is not on the mod portal, re-copied by `update-mods`
+        #[structopt(long)]
+        file: Vec<PathBuf>,
+
+        /// Unpacked mod directory to link, for mod development
+        #[structopt(long)]
+        dir: Vec<PathBuf>,
     },
     /// Adds server mods
     RemoveMod {
@@ -243,10 +640,46 @@ pub enum Args {
 
         mods: Vec<String>,
     },
+    /// Find mods on the mod portal
+    Mod {
+        #[structopt(subcommand)]
+        command: ModCommand,
+    },
     /// Update server mods
     UpdateMods {
         /// Name of the server
         name: String,
+
+        /// Show changelogs of available updates and ask before applying them
+        #[structopt(long)]
+        changelog: bool,
+    },
+    /// Measure how long game updates take, optionally comparing against
+    /// another Factorio version or the latest mod versions
+    Benchmark {
+        /// Name of the server
+        name: String,
+
+        /// Number of ticks to run
+        #[structopt(long, default_value = "1000")]
+        ticks: u32,
+
+        /// Factorio version to use instead of the one the server uses
+        #[structopt(long)]
+        version: Option<VersionReq>,
+
+        /// Run again with this Factorio version and compare the results
+        #[structopt(long)]
+        compare_version: Option<VersionReq>,
+
+        /// Run again with the latest versions of unpinned mods and compare the results
+        #[structopt(long)]
+        compare_latest_mods: bool,
+    },
+    /// Show what a save contains
+    Inspect {
+        /// Name of a server, or path to a save file
+        target: String,
     },
     /// Display server config
     Show {
@@ -278,4 +711,37 @@ pub enum Args {
         /// Name of the server
         name: String,
     },
+    /// Starts all servers marked with --autostart and supervises them
+    Up,
+    /// Creates and edits servers to match a manifest file
+    Apply {
+        /// Path to the manifest
+        path: PathBuf,
+
+        /// Only display the changes
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Generates systemd service units for servers
+    Systemd {
+        /// Name of the server
+        #[structopt(required_unless = "all")]
+        name: Option<String>,
+
+        /// Generate units for all servers
+        #[structopt(long, conflicts_with = "name")]
+        all: bool,
+
+        /// Generate system units instead of user units
+        #[structopt(long)]
+        system: bool,
+
+        /// Write units to the unit directory instead of printing them
+        #[structopt(long)]
+        install: bool,
+
+        /// Log level passed to the service in FACTS_LOG
+        #[structopt(long, default_value = "info")]
+        log_level: String,
+    },
 }
@@ -1,8 +1,9 @@
 use app_dirs::{AppDataType, AppInfo};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::error::{NoSuchWorld, WorldAlreadyExists};
+use crate::error::{NoSuchTemplate, NoSuchWorld, TemplateAlreadyExists, WorldAlreadyExists};
 use crate::version::Version;
 
 const APP_INFO: AppInfo = AppInfo {
@@ -14,6 +15,13 @@ pub fn app_root() -> PathBuf {
     app_dirs::app_root(AppDataType::UserData, &APP_INFO).expect("No data dir available")
 }
 
+/// Returns `settings.toml`, global settings of facts
+pub fn settings_file() -> PathBuf {
+    let mut pb = app_root();
+    pb.push("settings.toml");
+    pb
+}
+
 /// Creates directory `worlds/$name` and required subdirectories
 pub fn credentials_file() -> PathBuf {
     let mut pb = app_root();
@@ -54,6 +62,34 @@ pub fn get_world(name: &str) -> Result<PathBuf, NoSuchWorld> {
     }
 }
 
+/// Moves a world directory to `worlds/$name`
+pub fn rename_world(dir: &Path, name: &str) -> Result<PathBuf, WorldAlreadyExists> {
+    let mut pb = app_root();
+    pb.push("worlds");
+    pb.push(app_dirs::sanitized(name));
+
+    if pb.exists() {
+        Err(WorldAlreadyExists(name.to_owned()))
+    } else {
+        fs::rename(dir, &pb).expect("Could not move dir");
+        Ok(pb)
+    }
+}
+
+/// Recreates directory `scratch/$purpose`, used as write data directory
+/// when running Factorio outside of a world, e.g. for map previews
+pub fn scratch_dir(purpose: &str) -> PathBuf {
+    let mut pb = app_root();
+    pb.push("scratch");
+    pb.push(purpose);
+
+    if pb.exists() {
+        fs::remove_dir_all(&pb).expect("Could not delete dir");
+    }
+    fs::create_dir_all(&pb.join("factorio/mods")).expect("Could not create dir");
+    pb
+}
+
 /// Returns all folders under `worlds/`
 pub fn list_worlds() -> Vec<String> {
     let mut pb = app_root();
@@ -68,6 +104,67 @@ pub fn list_worlds() -> Vec<String> {
     }
 }
 
+/// Returns the UDP ports used by more than one world, with the worlds using them
+///
+/// `port_of` reads the port of a world, worlds it returns `None` for are skipped
+pub fn port_conflicts<F: Fn(&str) -> Option<u16>>(port_of: F) -> BTreeMap<u16, Vec<String>> {
+    let mut ports: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    for world in list_worlds() {
+        if let Some(port) = port_of(&world) {
+            ports.entry(port).or_default().push(world);
+        }
+    }
+    ports.retain(|_, worlds| worlds.len() > 1);
+    ports
+}
+
+/// Creates directory `templates/$name`, replacing an existing one if `force` is set
+pub fn new_template(name: &str, force: bool) -> Result<PathBuf, TemplateAlreadyExists> {
+    let mut pb = app_root();
+    pb.push("templates");
+
+    fs::create_dir_all(&pb).expect("Could not create dir");
+
+    pb.push(app_dirs::sanitized(name));
+
+    if pb.exists() {
+        if !force {
+            return Err(TemplateAlreadyExists(name.to_owned()));
+        }
+        fs::remove_dir_all(&pb).expect("Could not delete dir");
+    }
+
+    fs::create_dir(&pb).expect("Could not create dir");
+    Ok(pb)
+}
+
+/// Returns directory `templates/$name` if it exists
+pub fn get_template(name: &str) -> Result<PathBuf, NoSuchTemplate> {
+    let mut pb = app_root();
+    pb.push("templates");
+    pb.push(app_dirs::sanitized(name));
+
+    if pb.exists() {
+        Ok(pb)
+    } else {
+        Err(NoSuchTemplate(name.to_owned()))
+    }
+}
+
+/// Returns all folders under `templates/`
+pub fn list_templates() -> Vec<String> {
+    let mut pb = app_root();
+    pb.push("templates");
+
+    if let Ok(paths) = fs::read_dir(pb) {
+        paths
+            .map(|p| String::from(p.unwrap().path().file_name().unwrap().to_str().unwrap()))
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
 /// Returns all downloaded versions
 pub fn list_versions() -> Result<Vec<Version>, Box<dyn std::error::Error>> {
     let mut pb = app_root();
@@ -105,6 +202,22 @@ pub fn delete_version(version: Version) {
     fs::remove_dir_all(&pb).expect("Could not delete dir");
 }
 
+/// Returns `downloads/$file_name`, used for files that are unpacked after downloading
+pub fn download_file(file_name: &str) -> PathBuf {
+    let mut pb = app_root();
+    pb.push("downloads");
+
+    fs::create_dir_all(&pb).expect("Could not create dir");
+
+    pb.push(file_name);
+    pb
+}
+
+/// Returns `downloads/$file_name.part`, where interrupted downloads are kept to be resumed
+pub fn partial_download(file_name: &str) -> PathBuf {
+    download_file(&format!("{}.part", file_name))
+}
+
 pub fn create_mods_dir() {
     let mut pb = app_root();
     pb.push("mods");
@@ -1,13 +1,32 @@
+use lazy_static::lazy_static;
+use reqwest::header;
 use scraper::{Html, Selector};
 use serde_json::Value;
+use std::fs::{self, File};
 use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use tar::Archive;
 use xz2::read::XzDecoder;
 
-use crate::error::NoDownloadAvailable;
+use crate::dirs;
+use crate::error::{unsend, DownloadError, NoDownloadAvailable};
+use crate::http;
+use crate::progress::Progress;
+use crate::settings;
 use crate::version::{ResolvedVersionReq, Version, VersionReq};
 
-const INVALID_DATA: &str = "Invalid response from factorio API";
+/// How long fetched latest release numbers are reused, in seconds
+static LATEST_RELEASES_MAX_AGE: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    /// Latest release numbers shared by all servers of this process
+    static ref LATEST_RELEASES: Mutex<Option<(Instant, LatestReleases)>> = Mutex::new(None);
+
+    /// Prevents concurrent downloads of the same version
+    static ref DOWNLOAD_LOCK: Mutex<()> = Mutex::new(());
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LatestReleases {
@@ -15,33 +34,47 @@ pub struct LatestReleases {
     pub stable: Version,
 }
 impl LatestReleases {
+    /// Reuse fetched release numbers for this long, so that
+    /// multiple servers can share a single update check
+    pub fn set_max_age(max_age: Duration) {
+        LATEST_RELEASES_MAX_AGE.store(max_age.as_secs(), Ordering::SeqCst);
+    }
+
     pub fn get() -> Result<Self, Box<dyn std::error::Error>> {
-        let resp =
-            reqwest::blocking::get("https://factorio.com/api/latest-releases")?.json::<Value>()?;
+        let max_age = Duration::from_secs(LATEST_RELEASES_MAX_AGE.load(Ordering::SeqCst));
+
+        let mut cache = LATEST_RELEASES.lock().unwrap();
+        if let Some((fetched, releases)) = &*cache {
+            if fetched.elapsed() < max_age {
+                log::trace!("Using cached latest releases {:?}", releases);
+                return Ok(releases.clone());
+            }
+        }
+
+        let releases = Self::fetch()?;
+        *cache = Some((Instant::now(), releases.clone()));
+        Ok(releases)
+    }
+
+    fn fetch() -> Result<Self, Box<dyn std::error::Error>> {
+        let url = settings::urls().factorio("/api/latest-releases");
+        let resp = http::client().get(&url).send()?.json::<Value>()?;
 
         log::trace!("Requesting latest release numbers");
 
+        let headless = |branch: &str| {
+            resp.get(branch)
+                .and_then(|b| b.get("headless"))
+                .and_then(Value::as_str)
+                .and_then(|v| Version::try_from_str(v).ok())
+                .ok_or_else(|| DownloadError::InvalidResponse {
+                    url: url.clone(),
+                    reason: format!("no valid headless version for {}", branch),
+                })
+        };
         let s = Self {
-            experimental: Version::try_from_str(
-                &resp
-                    .get("experimental")
-                    .expect(INVALID_DATA)
-                    .get("headless")
-                    .expect(INVALID_DATA)
-                    .as_str()
-                    .expect(INVALID_DATA),
-            )
-            .expect(INVALID_DATA),
-            stable: Version::try_from_str(
-                &resp
-                    .get("stable")
-                    .expect(INVALID_DATA)
-                    .get("headless")
-                    .expect(INVALID_DATA)
-                    .as_str()
-                    .expect(INVALID_DATA),
-            )
-            .expect(INVALID_DATA),
+            experimental: headless("experimental")?,
+            stable: headless("stable")?,
         };
 
         log::trace!("Latest releases {:?}", s);
@@ -56,39 +89,36 @@ pub struct Release {
 }
 impl Release {
     pub fn url(&self) -> String {
-        format!(
-            "https://www.factorio.com/get-download/{}/headless/linux64",
-            self.version
-        )
+        settings::urls().factorio(&format!("/get-download/{}/headless/linux64", self.version))
     }
 
     fn get_all_from(url: &str) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
-        let resp = reqwest::blocking::get(url)?;
+        let resp = http::client().get(url).send()?;
         let document = Html::parse_document(&resp.text()?);
         let selector = Selector::parse("h3").unwrap();
-        Ok(document
+        let releases = document
             .select(&selector)
-            .map(|x| Self {
-                version: Version::try_from_str(
-                    &x.text()
-                        .next()
-                        .expect(INVALID_DATA)
-                        .split_whitespace()
-                        .next()
-                        .expect(INVALID_DATA)
-                        .to_owned(),
-                )
-                .expect(INVALID_DATA),
+            .map(|x| {
+                x.text()
+                    .next()
+                    .and_then(|t| t.split_whitespace().next())
+                    .and_then(|v| Version::try_from_str(v).ok())
+                    .map(|version| Self { version })
+                    .ok_or_else(|| DownloadError::InvalidResponse {
+                        url: url.to_owned(),
+                        reason: format!("invalid release {:?}", x.text().collect::<String>()),
+                    })
             })
-            .collect())
+            .collect::<Result<_, _>>()?;
+        Ok(releases)
     }
 
     pub fn get_stables() -> Result<Vec<Self>, Box<dyn std::error::Error>> {
-        Self::get_all_from("https://www.factorio.com/download-headless")
+        Self::get_all_from(&settings::urls().factorio("/download-headless"))
     }
 
     pub fn get_experimentals() -> Result<Vec<Self>, Box<dyn std::error::Error>> {
-        Self::get_all_from("https://www.factorio.com/download-headless/experimental")
+        Self::get_all_from(&settings::urls().factorio("/download-headless/experimental"))
     }
 
     pub fn get_all_by_hint(
@@ -114,6 +144,7 @@ pub fn require(version_req: VersionReq) -> Result<Version, Box<dyn std::error::E
 pub fn require_resolved(
     resolved: ResolvedVersionReq,
 ) -> Result<Version, Box<dyn std::error::Error>> {
+    let _lock = DOWNLOAD_LOCK.lock().unwrap();
     match resolved.version.location() {
         Ok(_location) => {
             log::info!("Factorio {} already downloaded", resolved.version);
@@ -143,23 +174,55 @@ pub fn require_resolved(
 }
 
 fn download_version(target_path: &Path, url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let response = reqwest::blocking::get(url)?;
-
-    assert!(
-        response
-            .headers()
-            .get("content-disposition")
-            .expect(INVALID_DATA)
-            .to_str()
-            .expect(INVALID_DATA)
-            .ends_with(".tar.xz"),
-        INVALID_DATA
-    );
-
-    let mut archive = Archive::new(XzDecoder::new(response));
-    archive
-        .unpack(target_path)
-        .expect("Unable to unpack archive");
+    let name = target_path.file_name().unwrap().to_str().unwrap();
+    let archive_path = dirs::download_file(&format!("factorio-headless_{}.tar.xz", name));
+    let unpack_path = dirs::download_file(&format!("factorio-headless_{}", name));
+
+    if !archive_path.exists() {
+        let client = http::client();
+        let progress = Progress::new(1);
+        http::download_file(
+            &client,
+            url,
+            &archive_path,
+            &progress,
+            |request| request,
+            |response| {
+                let archive = response
+                    .headers()
+                    .get(header::CONTENT_DISPOSITION)
+                    .and_then(|d| d.to_str().ok())
+                    .map_or(false, |d| d.trim_end_matches('"').ends_with(".tar.xz"));
+                if archive {
+                    Ok(())
+                } else {
+                    Err(Box::new(DownloadError::InvalidResponse {
+                        url: url.to_owned(),
+                        reason: "not a .tar.xz archive".to_owned(),
+                    }))
+                }
+            },
+        )
+        .map_err(unsend)?;
+        progress.finish();
+    }
+
+    // Unpack next to the archive first, an incomplete version directory would count as installed
+    if unpack_path.exists() {
+        fs::remove_dir_all(&unpack_path).map_err(DownloadError::Io)?;
+    }
+    let file = File::open(&archive_path).map_err(DownloadError::Io)?;
+    if let Err(error) = Archive::new(XzDecoder::new(file)).unpack(&unpack_path) {
+        // The archive is likely corrupt, download it again next time
+        let _ = fs::remove_file(&archive_path);
+        let _ = fs::remove_dir_all(&unpack_path);
+        return Err(Box::new(DownloadError::InvalidResponse {
+            url: url.to_owned(),
+            reason: format!("unable to unpack archive: {}", error),
+        }));
+    }
+    fs::rename(&unpack_path, target_path).map_err(DownloadError::Io)?;
+    fs::remove_file(&archive_path).map_err(DownloadError::Io)?;
 
     Ok(())
 }
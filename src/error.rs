@@ -38,12 +38,221 @@ impl fmt::Display for NoSuchWorld {
 }
 impl Error for NoSuchWorld {}
 
+#[derive(Debug)]
+#[must_use]
+pub struct TemplateAlreadyExists(pub String);
+impl fmt::Display for TemplateAlreadyExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Template {} already exists, use --force to overwrite", self.0)
+    }
+}
+impl Error for TemplateAlreadyExists {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct NoSuchTemplate(pub String);
+impl fmt::Display for NoSuchTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Template {} does not exist", self.0)
+    }
+}
+impl Error for NoSuchTemplate {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct InvalidBundle(pub String);
+impl fmt::Display for InvalidBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid bundle: {}", self.0)
+    }
+}
+impl Error for InvalidBundle {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct InvalidSave(pub String);
+impl fmt::Display for InvalidSave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid save file {}", self.0)
+    }
+}
+impl Error for InvalidSave {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct InvalidMod(pub String);
+impl fmt::Display for InvalidMod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid mod {}", self.0)
+    }
+}
+impl Error for InvalidMod {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct IncompatibleMods {
+    pub world: String,
+    pub version: Version,
+    /// Mod names and why they can't be used
+    pub mods: Vec<(String, String)>,
+}
+impl fmt::Display for IncompatibleMods {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Not updating {} to Factorio {}, some mods are not compatible:",
+            self.world, self.version
+        )?;
+        for (name, reason) in &self.mods {
+            writeln!(f, "  {}: {}", name, reason)?;
+        }
+        write!(
+            f,
+            "Remove these mods, or use `facts edit {} --allow-incompatible-mods true` \
+             to update anyway",
+            self.world
+        )
+    }
+}
+impl Error for IncompatibleMods {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct InvalidModSettings(pub String);
+impl fmt::Display for InvalidModSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid mod settings: {}", self.0)
+    }
+}
+impl Error for InvalidModSettings {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct SaveTooNew {
+    pub save: Version,
+    pub factorio: Version,
+}
+impl fmt::Display for SaveTooNew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Save is from Factorio {}, which is newer than the server version ({}). \
+             Use --factorio to select a newer version",
+            self.save, self.factorio
+        )
+    }
+}
+impl Error for SaveTooNew {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct MapPreviewFailed(pub String);
+impl fmt::Display for MapPreviewFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Map preview generation failed:\n{}", self.0)
+    }
+}
+impl Error for MapPreviewFailed {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct InvalidScenario(pub String);
+impl fmt::Display for InvalidScenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid scenario {:?}, expected a directory, a name or mod-name/scenario-name",
+            self.0
+        )
+    }
+}
+impl Error for InvalidScenario {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct BenchmarkFailed(pub String);
+impl fmt::Display for BenchmarkFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Benchmark failed:\n{}", self.0)
+    }
+}
+impl Error for BenchmarkFailed {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct NoSuchSnapshot {
+    pub name: String,
+    pub available: Vec<String>,
+}
+impl fmt::Display for NoSuchSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.available.is_empty() {
+            write!(f, "No snapshot {:?}, the server has no autosaves", self.name)
+        } else {
+            write!(
+                f,
+                "No snapshot {:?}, available ones are: {}",
+                self.name,
+                self.available.join(", ")
+            )
+        }
+    }
+}
+impl Error for NoSuchSnapshot {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct WorldIsRunning(pub String);
+impl fmt::Display for WorldIsRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "World {} is currently running", self.0)
+    }
+}
+impl Error for WorldIsRunning {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct PortAlreadyAllocated {
+    pub port: u16,
+    pub world: String,
+}
+impl fmt::Display for PortAlreadyAllocated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "UDP port {} is already used by world {}, choose another one with --port",
+            self.port, self.world
+        )
+    }
+}
+impl Error for PortAlreadyAllocated {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct NoFreePort;
+impl fmt::Display for NoFreePort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No free UDP port available, choose one with --port")
+    }
+}
+impl Error for NoFreePort {}
+
+#[derive(Debug)]
+#[must_use]
+pub struct NoSuchVersion(pub String);
+impl fmt::Display for NoSuchVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No Factorio release matches version {}", self.0)
+    }
+}
+impl Error for NoSuchVersion {}
+
 #[derive(Debug)]
 #[must_use]
 pub struct NoDownloadAvailable(pub Version);
 impl fmt::Display for NoDownloadAvailable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "No download link available for version {:?}", self.0)
+        write!(f, "No download link available for version {}", self.0)
     }
 }
 impl Error for NoDownloadAvailable {}
@@ -72,6 +281,16 @@ impl fmt::Display for NoMatchingModVersions {
 }
 impl Error for NoMatchingModVersions {}
 
+#[derive(Debug)]
+#[must_use]
+pub struct NoSuchModVersion(pub String, pub Version);
+impl fmt::Display for NoSuchModVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mod {:?} has no release {}", self.0, self.1)
+    }
+}
+impl Error for NoSuchModVersion {}
+
 #[derive(Debug)]
 #[must_use]
 pub struct DowngradingNotAllowed {
@@ -92,13 +311,17 @@ impl Error for DowngradingNotAllowed {}
 #[derive(Debug, Clone)]
 pub enum ServerError {
     PortUnavailable,
+    /// Factorio exited unsuccessfully, with its exit status
+    Crashed(String),
 }
 impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match self {
-            ServerError::PortUnavailable =>
-                "UDP port already in use. Is there another server running?",
-        })
+        match self {
+            ServerError::PortUnavailable => {
+                write!(f, "UDP port already in use. Is there another server running?")
+            },
+            ServerError::Crashed(status) => write!(f, "Server process crashed ({})", status),
+        }
     }
 }
 impl Error for ServerError {}
@@ -150,3 +373,77 @@ impl fmt::Display for LoginFailed {
     }
 }
 impl Error for LoginFailed {}
+
+#[derive(Debug, Clone)]
+pub struct InvalidServerSettings(pub String);
+impl fmt::Display for InvalidServerSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid server settings: {}", self.0)
+    }
+}
+impl Error for InvalidServerSettings {}
+
+#[derive(Debug, Clone)]
+pub struct ServersFailed(pub Vec<String>);
+impl fmt::Display for ServersFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Servers stopped with errors: {}", self.0.join(", "))
+    }
+}
+impl Error for ServersFailed {}
+
+#[derive(Debug, Clone)]
+pub struct InvalidManifest(pub String);
+impl fmt::Display for InvalidManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid manifest: {}", self.0)
+    }
+}
+impl Error for InvalidManifest {}
+
+#[derive(Debug, Clone)]
+pub struct InvalidSettings(pub String);
+impl fmt::Display for InvalidSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid settings.toml: {}", self.0)
+    }
+}
+impl Error for InvalidSettings {}
+
+/// Errors that can be passed between threads
+pub type SendError = Box<dyn Error + Send + Sync>;
+
+pub fn unsend(error: SendError) -> Box<dyn Error> {
+    error
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Status { url: String, status: u16 },
+    Failed { url: String, attempts: u32, reason: String },
+    InvalidResponse { url: String, reason: String },
+    Io(std::io::Error),
+}
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status { url, status } => {
+                write!(f, "Download of {} failed: server responded with {}", url, status)
+            },
+            Self::Failed {
+                url,
+                attempts,
+                reason,
+            } => write!(
+                f,
+                "Download of {} failed after {} attempts: {}",
+                url, attempts, reason
+            ),
+            Self::InvalidResponse { url, reason } => {
+                write!(f, "Invalid response when downloading {}: {}", url, reason)
+            },
+            Self::Io(error) => write!(f, "Could not store download: {}", error),
+        }
+    }
+}
+impl Error for DownloadError {}
@@ -0,0 +1,209 @@
+//! Downloads shared by Factorio and mod updates. Partial files are resumed with
+//! HTTP range requests, and failed transfers are retried with backoff.
+
+use lazy_static::lazy_static;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::{header, Certificate, Proxy, StatusCode};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::dirs;
+use crate::error::{DownloadError, InvalidSettings, SendError};
+use crate::progress::Progress;
+use crate::settings::{self, HttpSettings};
+
+/// Attempts before a download is given up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled for each further attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// Client shared by all requests, built from the global settings on first use
+    static ref CLIENT: Result<Client, InvalidSettings> = build_client(&settings::get().http);
+}
+
+/// Builds the shared client, see `settings::init`
+pub fn init() -> Result<(), InvalidSettings> {
+    CLIENT.as_ref().map(|_| ()).map_err(Clone::clone)
+}
+
+/// Shared client, cloning it is cheap
+pub fn client() -> Client {
+    CLIENT.as_ref().expect("Could not create client").clone()
+}
+
+fn build_client(settings: &HttpSettings) -> Result<Client, InvalidSettings> {
+    let invalid = |e: reqwest::Error| InvalidSettings(e.to_string());
+    let mut builder = ClientBuilder::new()
+        .cookie_store(true)
+        .user_agent(settings.user_agent.as_str())
+        .connect_timeout(Duration::from_secs(settings.connect_timeout))
+        .timeout(Duration::from_secs(settings.read_timeout));
+    if let Some(proxy) = &settings.proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(invalid)?);
+    }
+    if let Some(path) = &settings.ca_bundle {
+        let pem = fs::read(path).map_err(|e| InvalidSettings(format!("{:?}: {}", path, e)))?;
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem).map_err(invalid)?);
+    }
+    builder.build().map_err(invalid)
+}
+
+/// Outcome of a failed attempt
+enum Failure {
+    /// Network problems and server errors, the download is tried again
+    Retry(String),
+    Fatal(SendError),
+}
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Self {
+        Self::Fatal(Box::new(DownloadError::Io(error)))
+    }
+}
+
+/// Downloads `url` to `target`. Data is written to a partial file in the downloads
+/// directory first, so that a later call continues where an interrupted one stopped.
+/// `request` can add parameters, e.g. credentials, which are not shown in messages.
+/// `check` rejects responses that are not the expected file, these are not retried.
+pub fn download_file(
+    client: &Client, url: &str, target: &Path, progress: &Progress,
+    request: impl Fn(RequestBuilder) -> RequestBuilder,
+    check: impl Fn(&Response) -> Result<(), SendError>,
+) -> Result<(), SendError> {
+    let name = target
+        .file_name()
+        .and_then(|n| n.to_str())
+        .expect("Download target needs a file name");
+    let part = dirs::partial_download(name);
+
+    let mut id = None;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = transfer(client, url, &part, progress, &mut id, &request, &check);
+        match result {
+            Ok(()) => {
+                fs::rename(&part, target).map_err(DownloadError::Io)?;
+                if let Some(id) = id {
+                    progress.finish_file(id);
+                }
+                return Ok(());
+            },
+            Err(Failure::Fatal(error)) => return Err(error),
+            Err(Failure::Retry(reason)) if attempt < MAX_ATTEMPTS => {
+                let delay = INITIAL_BACKOFF * 2u32.pow(attempt - 1);
+                log::warn!(
+                    "Downloading {} failed: {}, retrying in {} s",
+                    name,
+                    reason,
+                    delay.as_secs()
+                );
+                thread::sleep(delay);
+            },
+            Err(Failure::Retry(reason)) => {
+                return Err(Box::new(DownloadError::Failed {
+                    url: url.to_owned(),
+                    attempts: MAX_ATTEMPTS,
+                    reason,
+                }));
+            },
+        }
+    }
+    unreachable!()
+}
+
+/// Single attempt, appending to the partial file if the server supports ranges
+fn transfer(
+    client: &Client, url: &str, part: &Path, progress: &Progress, id: &mut Option<usize>,
+    request: &impl Fn(RequestBuilder) -> RequestBuilder,
+    check: &impl Fn(&Response) -> Result<(), SendError>,
+) -> Result<(), Failure> {
+    let existing = fs::metadata(part).map_or(0, |m| m.len());
+
+    let mut builder = request(client.get(url));
+    if existing > 0 {
+        builder = builder.header(header::RANGE, format!("bytes={}-", existing));
+    }
+    let response = builder.send().map_err(|e| Failure::Retry(describe(&e)))?;
+
+    let status = response.status();
+    let resume = match status {
+        StatusCode::PARTIAL_CONTENT => true,
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // The partial file doesn't belong to the current file, start over
+            fs::remove_file(part)?;
+            return Err(Failure::Retry("partial download is invalid".to_owned()));
+        },
+        s if s.is_success() => false,
+        s if s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS => {
+            return Err(Failure::Retry(format!("server responded with {}", s)));
+        },
+        s => {
+            return Err(Failure::Fatal(Box::new(DownloadError::Status {
+                url: url.to_owned(),
+                status: s.as_u16(),
+            })));
+        },
+    };
+    check(&response).map_err(Failure::Fatal)?;
+
+    let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(part)?;
+    if resume {
+        log::trace!("Resuming download of {:?} at {} bytes", part, existing);
+        file.seek(SeekFrom::End(0))?;
+    } else {
+        file.set_len(0)?;
+    }
+
+    let remaining = response.content_length();
+    let id = match *id {
+        Some(id) => {
+            if !resume {
+                progress.reset(id, remaining);
+            }
+            id
+        },
+        None => {
+            let name = part.file_stem().and_then(|n| n.to_str()).unwrap_or_default();
+            let new = progress.start(name, remaining);
+            *id = Some(new);
+            new
+        },
+    };
+
+    // Only reading is retried, failing to write the file (e.g. a full disk) is fatal
+    let mut reader = progress.reader(id, response);
+    let mut buf = vec![0; 64 * 1024];
+    let mut written = 0;
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(Failure::Retry(format!("connection interrupted ({})", e.kind())));
+            },
+        };
+        file.write_all(&buf[..len])?;
+        written += len as u64;
+    }
+    if remaining.map_or(false, |r| written < r) {
+        return Err(Failure::Retry("connection closed early".to_owned()));
+    }
+    Ok(())
+}
+
+/// Reason for a failed request, without the URL which may contain credentials
+fn describe(error: &reqwest::Error) -> String {
+    if error.is_timeout() {
+        "timed out".to_owned()
+    } else if error.is_connect() {
+        "could not connect".to_owned()
+    } else if let Some(status) = error.status() {
+        format!("server responded with {}", status)
+    } else {
+        "request failed".to_owned()
+    }
+}
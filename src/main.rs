@@ -4,22 +4,44 @@
 // Nightly features
 #![feature(never_type)]
 
+mod benchmark;
+mod changelog;
 mod config;
 mod dirs;
 mod download;
 mod error;
+mod http;
+mod manifest;
+mod mod_settings;
 mod modportal;
+mod player_lists;
+mod progress;
+mod save;
 mod server;
 mod server_process;
+mod server_settings;
+mod settings;
+mod systemd;
+mod template;
 mod version;
 
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 
 use crate::config::*;
-use crate::error::OutputFileAlreadyExists;
-use crate::server::Server;
+use crate::download::LatestReleases;
+use crate::error::{InvalidModSettings, OutputFileAlreadyExists, ServersFailed};
+use crate::modportal::{ModDownloader, ModPortal};
+use crate::player_lists::PlayerList;
+use crate::save::SaveHeader;
+use crate::server::{LocalMod, Server};
+use crate::template::Template;
+use crate::version::{Version, VersionReq};
 
 #[cfg(not(unix))]
 compile_error!("Non-unixy systems are not supported");
@@ -51,27 +73,94 @@ fn main(args: Args) {
     })
     .expect("Error setting Ctrl-C handler");
 
+    if let Err(e) = settings::init() {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+
     let result = match args {
-        Args::Create { name, config } => cmd_create(&name, config),
+        Args::Create {
+            name,
+            template,
+            preview,
+            seed,
+            scenario,
+            args,
+        } => cmd_create(&name, template, preview, seed, scenario, args),
+        Args::Preview {
+            output,
+            seed,
+            template,
+            args,
+        } => cmd_preview(&output, seed, template, args),
+        Args::Template { command } => cmd_template(command),
         Args::Import {
             name,
             path,
+            bundle,
+            add_save_mods,
             config,
             meta,
-        } => cmd_import(&name, &path, config, meta),
-        Args::Export { name, path, force } => cmd_export(&name, &path, force),
+        } => cmd_import(&name, &path, bundle, add_save_mods, config, meta),
+        Args::Clone {
+            source,
+            name,
+            at_snapshot,
+        } => cmd_clone(&source, &name, at_snapshot),
+        Args::Rename {
+            name,
+            new_name,
+            manifest,
+        } => cmd_rename(&name, &new_name, &manifest),
+        Args::Export {
+            name,
+            path,
+            bundle,
+            include_mods,
+            force,
+        } => cmd_export(&name, &path, bundle, include_mods, force),
         Args::Edit { name, config, meta } => cmd_edit(&name, config, meta),
+        Args::Settings { name, command } => cmd_settings(&name, command),
+        Args::ModSettings { name, command } => cmd_mod_settings(&name, command),
+        Args::Admins { name, command } => cmd_player_list(&name, PlayerList::Admins, command),
+        Args::Whitelist { name, command } => {
+            cmd_player_list(&name, PlayerList::Whitelist, command)
+        },
+        Args::Bans { name, command } => cmd_bans(&name, command),
         Args::Update { name } => cmd_update(&name),
         Args::Delete { name, force } => cmd_delete(&name, force),
         Args::Login { credentials } => cmd_login(credentials),
         Args::ListMods { name } => cmd_list_mods(&name),
-        Args::AddMod { name, mods } => cmd_add_mod(&name, mods),
+        Args::AddMod {
+            name,
+            mods,
+            file,
+            dir,
+        } => cmd_add_mod(&name, mods, &file, &dir),
         Args::RemoveMod { name, mods } => cmd_remove_mod(&name, mods),
-        Args::UpdateMods { name } => cmd_update_mods(&name),
+        Args::UpdateMods { name, changelog } => cmd_update_mods(&name, changelog),
+        Args::Mod { command } => cmd_mod(command),
+        Args::Benchmark {
+            name,
+            ticks,
+            version,
+            compare_version,
+            compare_latest_mods,
+        } => cmd_benchmark(&name, ticks, version, compare_version, compare_latest_mods),
+        Args::Inspect { target } => cmd_inspect(&target),
         Args::Show { name } => cmd_show(&name),
         Args::List { extended } => cmd_list(extended),
         Args::Prune => cmd_prune(),
         Args::Start { name } => cmd_start(&name),
+        Args::Up => cmd_up(),
+        Args::Apply { path, dry_run } => cmd_apply(&path, dry_run),
+        Args::Systemd {
+            name,
+            all,
+            system,
+            install,
+            log_level,
+        } => cmd_systemd(name, all, system, install, &log_level),
     };
 
     match result {
@@ -83,27 +172,197 @@ fn main(args: Args) {
     }
 }
 
-fn cmd_create(name: &str, config: CreateConfig) -> Result<(), Box<dyn std::error::Error>> {
-    Server::create(name.to_owned(), config)?;
+/// Config, mods and pinned mods from an optional template and arguments overriding it
+fn create_config(
+    template: Option<String>, args: CreateArgs,
+) -> Result<(CreateConfig, Vec<String>, BTreeMap<String, Version>), Box<dyn std::error::Error>> {
+    let (config, mods, pinned_mods) = if let Some(template) = template {
+        let template = Template::get(template)?;
+        (
+            template.create_config(),
+            template.info.mods.clone(),
+            template.info.pinned_mods.clone(),
+        )
+    } else {
+        (CreateConfig::default(), Vec::new(), BTreeMap::new())
+    };
+    Ok((args.apply(config), mods, pinned_mods))
+}
+
+fn cmd_create(
+    name: &str, template: Option<String>, preview: bool, seed: Option<u32>,
+    scenario: Option<String>, args: CreateArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (config, mods, pinned_mods) = create_config(template, args)?;
+
+    let mut seed = seed;
+    if preview {
+        // The world has to be generated from the previewed seed
+        if seed.is_none() && !has_fixed_seed(&config)? {
+            seed = Some(random_seed());
+        }
+        let path = PathBuf::from(format!("{}-preview.png", name));
+        Server::preview_map(&config, mods.clone(), &pinned_mods, seed, &path)?;
+        println!("Create the world with these settings? [y/N]");
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        if !line.trim().eq_ignore_ascii_case("y") {
+            println!("Cancelled");
+            return Ok(());
+        }
+    }
+
+    Server::create_with_mods(
+        name.to_owned(),
+        config,
+        mods,
+        pinned_mods,
+        scenario.as_deref(),
+        seed,
+    )?;
+    Ok(())
+}
+
+/// Whether map-gen-settings choose the seed, instead of leaving it random
+fn has_fixed_seed(config: &CreateConfig) -> Result<bool, Box<dyn std::error::Error>> {
+    let path = match &config.map_gen_settings {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let settings: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    Ok(settings.get("seed").map_or(false, serde_json::Value::is_number))
+}
+
+/// Seed picked by facts, so that it's known before the map is generated
+fn random_seed() -> u32 {
+    // Hashers are randomly keyed per process
+    RandomState::new().build_hasher().finish() as u32
+}
+
+fn cmd_preview(
+    output: &Path, seed: Option<u32>, template: Option<String>, args: CreateArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (config, mods, pinned_mods) = create_config(template, args)?;
+    Server::preview_map(&config, mods, &pinned_mods, seed, output)
+}
+
+fn cmd_template(command: TemplateCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        TemplateCommand::Save {
+            template,
+            from,
+            force,
+            args,
+        } => {
+            let (config, mods, pinned_mods) = if let Some(world) = from {
+                Template::capture(&Server::get(world)?)
+            } else {
+                (CreateConfig::default(), Vec::new(), BTreeMap::new())
+            };
+            Template::save(template, args.apply(config), mods, pinned_mods, force)?;
+        },
+        TemplateCommand::List => {
+            let mut templates = dirs::list_templates();
+            templates.sort();
+            for template in templates {
+                println!("{}", template);
+            }
+        },
+        TemplateCommand::Delete { template } => Template::get(template)?.delete(),
+    }
     Ok(())
 }
 
 fn cmd_import(
-    name: &str, path: &Path, config: ImportConfig, meta: MetaConfig,
+    name: &str, path: &Path, bundle: bool, add_save_mods: bool, config: ImportConfig,
+    meta: MetaConfigUpdate,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if bundle {
+        Server::import_bundle(name.to_owned(), path, &config, &meta)?;
+        return Ok(());
+    }
+
+    let mut defaults = MetaConfig::default();
+    defaults.apply_update(meta);
+    Server::import(name.to_owned(), path, config, defaults, |missing| {
+        println!("The save uses mods that are not installed:");
+        for save_mod in missing {
+            println!("{} {}", save_mod.name, save_mod.version);
+        }
+        if add_save_mods {
+            return Ok(true);
+        }
+
+        println!("Install them now? [y/N]");
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        if line.trim().eq_ignore_ascii_case("y") {
+            Ok(true)
+        } else {
+            println!("Add them later with `facts add-mod {} MOD`", name);
+            Ok(false)
+        }
+    })?;
+    Ok(())
+}
+
+fn cmd_clone(
+    source: &str, name: &str, snapshot: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let server = Server::create_empty(name.to_owned(), config, meta)?;
-    std::fs::copy(path, server.dir.join("world.zip"))?;
+    let server = Server::get(source.to_owned())?;
+    server.clone_as(name.to_owned(), snapshot.as_deref())?;
     Ok(())
 }
 
-fn cmd_export(name: &str, path: &Path, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+fn cmd_rename(
+    name: &str, new_name: &str, manifests: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::get(name.to_owned())?;
+    let old = server.clone();
+    let server = server.rename(new_name.to_owned())?;
+    log::info!("Moved {:?} to {:?}", old.dir, server.dir);
+
+    let units = match systemd::rename_units(&old, &server) {
+        Ok(units) => units,
+        Err(error) => {
+            // Keep the world where its units expect it
+            server.rename(name.to_owned())?;
+            log::info!("Moved {:?} back", old.dir);
+            return Err(Box::new(error));
+        },
+    };
+    for (path, _) in &units {
+        log::info!("Replaced unit with {:?}", path);
+    }
+    for system in units.iter().map(|(_, system)| *system) {
+        let systemctl = if system { "systemctl" } else { "systemctl --user" };
+        println!("Run `{} daemon-reload` to pick up the renamed unit", systemctl);
+    }
+
+    for path in manifests {
+        if manifest::Manifest::rename_world(path, name, new_name)? {
+            log::info!("Renamed server in {:?}", path);
+        } else {
+            log::warn!("Server {} not found in {:?}", name, path);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_export(
+    name: &str, path: &Path, bundle: bool, include_mods: bool, force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let server = Server::get(name.to_owned())?;
 
     if path.exists() && !force {
         return Err(Box::new(OutputFileAlreadyExists(path.to_owned())));
     }
 
-    std::fs::copy(server.dir.join("world.zip"), path)?;
+    if bundle {
+        server.export_bundle(path, include_mods)?;
+    } else {
+        std::fs::copy(server.dir.join("world.zip"), path)?;
+    }
     Ok(())
 }
 
@@ -115,12 +374,118 @@ fn cmd_edit(
     Ok(())
 }
 
+fn cmd_settings(name: &str, command: SettingsCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::get(name.to_owned())?;
+    let mut settings = server.server_settings()?;
+    match command {
+        SettingsCommand::Get { key: None } => {
+            println!("{}", serde_json::to_string_pretty(&settings.to_json_masked())?);
+        },
+        SettingsCommand::Get { key: Some(key) } => match settings.get(&key)? {
+            serde_json::Value::String(s) => println!("{}", s),
+            value => println!("{}", serde_json::to_string_pretty(&value)?),
+        },
+        SettingsCommand::Set { key, value } => {
+            settings.set(&key, &value)?;
+            server.set_server_settings(&settings);
+        },
+    }
+    Ok(())
+}
+
+fn cmd_mod_settings(
+    name: &str, command: ModSettingsCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::get(name.to_owned())?;
+    let mut settings = server.mod_settings()?;
+    match command {
+        ModSettingsCommand::List => {
+            for (scope, setting, value) in settings.values() {
+                println!("{} {} = {}", scope, setting, value.to_json());
+            }
+        },
+        ModSettingsCommand::Get { setting } => match settings.get(&setting) {
+            Some((_, value)) => match value.to_json() {
+                serde_json::Value::String(s) => println!("{}", s),
+                value => println!("{}", serde_json::to_string_pretty(&value)?),
+            },
+            None => {
+                return Err(Box::new(InvalidModSettings(format!(
+                    "No setting named {}",
+                    setting
+                ))));
+            },
+        },
+        ModSettingsCommand::Set {
+            setting,
+            value,
+            scope,
+        } => {
+            settings.set(&setting, &value, scope.as_deref())?;
+            server.set_mod_settings(&settings);
+        },
+        ModSettingsCommand::Import { path } => server.import_mod_settings(&path)?,
+        ModSettingsCommand::Export { path, force } => {
+            if path.exists() && !force {
+                return Err(Box::new(OutputFileAlreadyExists(path)));
+            }
+            if path.extension() == Some("json".as_ref()) {
+                std::fs::write(path, serde_json::to_string_pretty(&settings.to_json())?)?;
+            } else {
+                std::fs::write(path, settings.to_bytes())?;
+            }
+        },
+    }
+    Ok(())
+}
+
+fn cmd_player_list(
+    name: &str, list: PlayerList, command: PlayerListCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::get(name.to_owned())?;
+    match command {
+        PlayerListCommand::Add { players } => server.add_to_player_list(list, &players)?,
+        PlayerListCommand::Remove { players } => server.remove_from_player_list(list, &players)?,
+        PlayerListCommand::List => {
+            for player in server.player_list(list)? {
+                println!("{}", player);
+            }
+        },
+    }
+
+    if list == PlayerList::Whitelist && !server.info.config.use_server_whitelist {
+        log::warn!(
+            "Whitelist is not in use, enable it with `facts edit {} --use-server-whitelist true`",
+            name
+        );
+    }
+    Ok(())
+}
+
+fn cmd_bans(name: &str, command: BanListCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::get(name.to_owned())?;
+    match command {
+        BanListCommand::Add { player, reason } => server.ban(&player, reason)?,
+        BanListCommand::Remove { players } => server.unban(&players)?,
+        BanListCommand::List => {
+            for ban in server.bans()? {
+                match ban.reason {
+                    Some(reason) => println!("{:<20} {}", ban.username, reason),
+                    None => println!("{}", ban.username),
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
 fn cmd_update(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let mut server = Server::get(name.to_owned())?;
     if let Some(resolved) = server.update_available() {
+        server.check_mod_compatibility(resolved.version)?;
         server.update(resolved)?;
     }
-    cmd_update_mods(name)?;
+    cmd_update_mods(name, false)?;
     Ok(())
 }
 
@@ -147,34 +512,270 @@ fn cmd_delete(name: &str, force: bool) -> Result<(), Box<dyn std::error::Error>>
 }
 
 fn cmd_login(credentials: LoginCredentials) -> Result<(), Box<dyn std::error::Error>> {
-    let downloader = modportal::ModDownloader::new()?;
-    downloader.login(credentials)?;
-    Ok(())
+    modportal::ModDownloader::login(credentials)
 }
 
 fn cmd_list_mods(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let server = Server::get(name.to_owned())?;
     for mod_info in server.mods() {
-        println!("{} {}", mod_info.name, mod_info.version);
+        match server.info.local_mods.get(&mod_info.name) {
+            Some(LocalMod::File(path)) => {
+                println!("{} {} (from {:?})", mod_info.name, mod_info.version, path)
+            },
+            _ => println!("{} {}", mod_info.name, mod_info.version),
+        }
+    }
+    for (mod_name, source) in &server.info.local_mods {
+        if let LocalMod::Dir(path) = source {
+            println!("{} (linked {:?})", mod_name, path);
+        }
     }
     Ok(())
 }
 
-fn cmd_add_mod(name: &str, mods: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let server = Server::get(name.to_owned())?;
+fn cmd_add_mod(
+    name: &str, mods: Vec<String>, files: &[PathBuf], dirs: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut server = Server::get(name.to_owned())?;
+    for path in files {
+        server.add_mod_file(path)?;
+    }
+    for path in dirs {
+        server.add_mod_dir(path)?;
+    }
     server.add_mods(mods)
 }
 
 fn cmd_remove_mod(name: &str, mods: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
-    let server = Server::get(name.to_owned())?;
+    let mut server = Server::get(name.to_owned())?;
     server.remove_mods(mods)
 }
 
-fn cmd_update_mods(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn cmd_update_mods(name: &str, changelog: bool) -> Result<(), Box<dyn std::error::Error>> {
     let server = Server::get(name.to_owned())?;
+
+    if changelog {
+        let portal = ModPortal::new();
+        let mut updates = 0;
+        for mod_info in server.mods() {
+            if server.info.pinned_mods.contains_key(&mod_info.name)
+                || server.info.local_mods.contains_key(&mod_info.name)
+            {
+                continue;
+            }
+
+            let details = portal.info(&mod_info.name)?;
+            let candidate =
+                match modportal::latest_compatible(&details.releases, server.info.current_version) {
+                    Some(release) => Version::try_from_str(&release.version)?,
+                    None => continue,
+                };
+            if candidate <= mod_info.version {
+                continue;
+            }
+
+            updates += 1;
+            println!("{} {} -> {}", mod_info.name, mod_info.version, candidate);
+            let entries = changelog::parse(&details.changelog);
+            for entry in changelog::between(&entries, Some(mod_info.version), Some(candidate)) {
+                println!("{}", entry);
+            }
+            println!();
+        }
+
+        if updates == 0 {
+            println!("All mods are up to date");
+            return Ok(());
+        }
+        println!("Apply {} mod updates? [y/N]", updates);
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        if !line.trim().eq_ignore_ascii_case("y") {
+            println!("Cancelled");
+            return Ok(());
+        }
+    }
+
     server.update_mods()
 }
 
+fn cmd_mod(command: ModCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let portal = ModPortal::new();
+    match command {
+        ModCommand::Search { query, limit } => {
+            let results = portal.search(&query.join(" "))?;
+            if results.is_empty() {
+                println!("No mods found");
+            }
+            for result in results.iter().take(limit) {
+                let latest = result
+                    .latest_release
+                    .as_ref()
+                    .map(|r| format!("{} (Factorio {})", r.version, r.info_json.factorio_version))
+                    .unwrap_or_default();
+                println!(
+                    "{:<30} {:<40} {:>9}  {}",
+                    result.name, result.title, result.downloads_count, latest
+                );
+            }
+            if results.len() > limit {
+                println!("{} more results, use --limit to show them", results.len() - limit);
+            }
+        },
+        ModCommand::Info { name, world } => {
+            let info = portal.info(&name)?;
+            println!("name:      {}", info.name);
+            println!("title:     {}", info.title);
+            println!("owner:     {}", info.owner);
+            println!("downloads: {}", info.downloads_count);
+            println!("summary:   {}", info.summary);
+
+            // Releases are ordered from oldest to newest
+            let mut latest = BTreeMap::new();
+            for release in &info.releases {
+                latest.insert(release.info_json.factorio_version.clone(), release);
+            }
+            println!("latest releases:");
+            for (factorio_version, release) in &latest {
+                println!("  Factorio {:<6} {}", factorio_version, release.version);
+            }
+
+            if let Some(release) = info.releases.last() {
+                println!("dependencies of {}:", release.version);
+                for dependency in &release.info_json.dependencies {
+                    println!("  {}", dependency);
+                }
+            }
+
+            if let Some(world) = world {
+                let server = Server::get(world)?;
+                let version = server.info.current_version;
+                match modportal::latest_compatible(&info.releases, version) {
+                    Some(release) => println!(
+                        "compatible with {} (Factorio {}): {}",
+                        server.name, version, release.version
+                    ),
+                    None => println!(
+                        "not compatible with {} (Factorio {})",
+                        server.name, version
+                    ),
+                }
+            }
+        },
+        ModCommand::Changelog { name, from, to } => {
+            let info = portal.info(&name)?;
+            let entries = changelog::parse(&info.changelog);
+            let selected = changelog::between(&entries, from, to);
+            if selected.is_empty() {
+                println!("No changelog entries found");
+            }
+            for entry in selected {
+                println!("{}", entry);
+            }
+        },
+    }
+    Ok(())
+}
+
+fn cmd_benchmark(
+    name: &str, ticks: u32, version: Option<VersionReq>, compare_version: Option<VersionReq>,
+    compare_latest_mods: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::get(name.to_owned())?;
+    let version = match version {
+        Some(req) => download::require(req)?,
+        None => server.info.current_version,
+    };
+    let mods = server.mods();
+
+    let mut runs = vec![(
+        format!("Factorio {}, current mods", version),
+        server.benchmark(version, &mods, ticks)?,
+    )];
+
+    if compare_version.is_some() || compare_latest_mods {
+        let other_version = match compare_version {
+            Some(req) => download::require(req)?,
+            None => version,
+        };
+        let (other_mods, mods_label) = if compare_latest_mods {
+            let downloader = ModDownloader::new()?;
+            let mut latest = Vec::new();
+            for mod_info in mods {
+                if server.info.pinned_mods.contains_key(&mod_info.name)
+                    || server.info.local_mods.contains_key(&mod_info.name)
+                {
+                    latest.push(mod_info);
+                } else {
+                    latest.push(downloader.require(&mod_info.name, other_version)?);
+                }
+            }
+            (latest, "latest mods")
+        } else {
+            (mods, "current mods")
+        };
+        runs.push((
+            format!("Factorio {}, {}", other_version, mods_label),
+            server.benchmark(other_version, &other_mods, ticks)?,
+        ));
+    }
+
+    println!(
+        "{:<36} {:>8} {:>8} {:>8} {:>8}",
+        "", "avg ms", "min ms", "max ms", "UPS"
+    );
+    for (label, result) in &runs {
+        println!(
+            "{:<36} {:>8.3} {:>8.3} {:>8.3} {:>8.1}",
+            label,
+            result.avg_ms,
+            result.min_ms,
+            result.max_ms,
+            result.ups()
+        );
+    }
+    if let [(_, first), (_, second)] = runs.as_slice() {
+        println!(
+            "Average tick time change: {:+.1}%",
+            (second.avg_ms / first.avg_ms - 1.0) * 100.0
+        );
+    }
+    Ok(())
+}
+
+fn cmd_inspect(target: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let path = if Path::new(target).is_file() {
+        PathBuf::from(target)
+    } else {
+        Server::get(target.to_owned())?.dir.join("world.zip")
+    };
+
+    let save = SaveHeader::read(&path)?;
+    let size = std::fs::metadata(&path)?.len();
+    println!("file:     {:?}", path);
+    println!("size:     {:.1} MiB", size as f64 / (1024.0 * 1024.0));
+    println!("factorio: {}", save.version);
+    if save.campaign.is_empty() {
+        println!("scenario: {}", save.level_name);
+    } else {
+        println!("scenario: {} ({})", save.level_name, save.campaign);
+    }
+    if let Some(saved_at) = &save.saved_at {
+        println!("saved:    {}", saved_at);
+    }
+    if let Some(map_tick) = save.map_tick {
+        println!("tick:     {}", map_tick);
+    }
+    if let Some(playtime) = save.playtime() {
+        println!("playtime: {}", playtime);
+    }
+    println!("mods:");
+    for mod_info in save.user_mods() {
+        println!("  {} {}", mod_info.name, mod_info.version);
+    }
+    Ok(())
+}
+
 fn cmd_show(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let server = Server::get(name.to_owned())?;
     println!("name:       {}", server.name);
@@ -182,6 +783,14 @@ fn cmd_show(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     println!("required:   {:?}", server.info.config.factorio);
     println!("current:    {}", server.info.current_version);
     println!("autoupdate: {:?}", server.info.config.autoupdate);
+    println!("autostart:  {}", server.info.config.autostart);
+    if let Some(scenario) = &server.info.scenario {
+        println!("scenario:   {}", scenario);
+    }
+    println!("port:       {}", server.port());
+    if let Some(bind) = server.info.config.bind {
+        println!("bind:       {}", bind);
+    }
     Ok(())
 }
 
@@ -192,13 +801,25 @@ fn cmd_list(extended: bool) -> Result<(), Box<dyn std::error::Error>> {
         if extended {
             let server = Server::get(world.clone())?;
             println!(
-                "{:<20} {}  [{}]",
-                world, server.info.current_version, server.info.config.factorio
+                "{:<20} {:<5}  {}  [{}]",
+                world,
+                server.port(),
+                server.info.current_version,
+                server.info.config.factorio
             );
         } else {
             println!("{}", world);
         }
     }
+    let conflicts = dirs::port_conflicts(|world| {
+        Server::get(world.to_owned())
+            .ok()
+            .map(|server| server.port())
+    });
+    for (port, mut worlds) in conflicts {
+        worlds.sort();
+        log::warn!("UDP port {} is used by worlds {}", port, worlds.join(", "));
+    }
     Ok(())
 }
 
@@ -227,3 +848,122 @@ fn cmd_start(name: &str) -> Result<(), Box<dyn std::error::Error>> {
     server.run()?;
     Ok(())
 }
+
+fn cmd_up() -> Result<(), Box<dyn std::error::Error>> {
+    let mut servers = Vec::new();
+    for world in dirs::list_worlds() {
+        let server = Server::get(world)?;
+        if server.info.config.autostart {
+            servers.push(server);
+        }
+    }
+
+    if servers.is_empty() {
+        log::warn!("No servers marked for autostart, use `facts edit NAME --autostart true`");
+        return Ok(());
+    }
+
+    // Share update checks between servers
+    if let Some(minutes) = servers
+        .iter()
+        .map(|s| s.info.config.autoupdate_interval_minutes)
+        .min()
+    {
+        LatestReleases::set_max_age(Duration::from_secs(60 * minutes));
+    }
+
+    SIGINT_CATCH.store(true, Ordering::SeqCst);
+
+    let handles: Vec<_> = servers
+        .into_iter()
+        .map(|mut server| {
+            let name = server.name.clone();
+            let handle = thread::Builder::new()
+                .name(name.clone())
+                .spawn(move || server.run().map_err(|e| e.to_string()))
+                .expect("Could not start server thread");
+            (name, handle)
+        })
+        .collect();
+
+    let mut failed = Vec::new();
+    for (name, handle) in handles {
+        match handle.join() {
+            Ok(Ok(())) => log::info!("Server {} stopped", name),
+            Ok(Err(error)) => {
+                log::error!("Server {} failed: {}", name, error);
+                failed.push(name);
+            },
+            Err(_) => {
+                log::error!("Server {} crashed", name);
+                failed.push(name);
+            },
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::new(ServersFailed(failed)))
+    }
+}
+
+fn cmd_apply(path: &Path, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = manifest::Manifest::load(path)?;
+    let actions = manifest.plan()?;
+
+    if actions.is_empty() {
+        println!("Nothing to do");
+        return Ok(());
+    }
+
+    for action in &actions {
+        println!("{}", action);
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    for action in actions {
+        log::info!("{}", action);
+        action.apply()?;
+    }
+    Ok(())
+}
+
+fn cmd_systemd(
+    name: Option<String>, all: bool, system: bool, install: bool, log_level: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut servers = Vec::new();
+    if all {
+        let mut worlds = dirs::list_worlds();
+        worlds.sort();
+        for world in worlds {
+            servers.push(Server::get(world)?);
+        }
+    } else {
+        servers.push(Server::get(name.expect("Name required"))?);
+    }
+
+    for server in servers {
+        let unit = systemd::unit(&server, system, log_level);
+        if install {
+            let path = systemd::unit_dir(system).join(systemd::unit_name(&server));
+            std::fs::write(&path, unit)?;
+            log::info!("Installed {:?}", path);
+        } else {
+            println!("# {}", systemd::unit_name(&server));
+            println!("{}", unit);
+        }
+    }
+
+    if install {
+        let systemctl = if system { "systemctl" } else { "systemctl --user" };
+        println!(
+            "Run `{} daemon-reload` and `{} enable --now UNIT` to start the servers",
+            systemctl, systemctl
+        );
+    }
+    Ok(())
+}
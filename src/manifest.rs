@@ -0,0 +1,487 @@
+//! Declarative description of multiple servers, applied with `facts apply`
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::config::*;
+use crate::error::{InvalidManifest, NoSuchWorld};
+use crate::player_lists::{self, PlayerList};
+use crate::server::Server;
+use crate::server_settings::ServerSettings;
+use crate::version::{Version, VersionReq};
+
+/// Mod version requirement used in place of a pinned version
+const LATEST: &str = "latest";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub worlds: BTreeMap<String, WorldManifest>,
+}
+
+/// Desired state of a single world, fields that are left out are not managed
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorldManifest {
+    pub factorio: Option<String>,
+    pub autoupdate: Option<String>,
+    pub autoupdate_interval_minutes: Option<u64>,
+    pub use_server_whitelist: Option<bool>,
+    pub port: Option<u16>,
+    pub bind: Option<IpAddr>,
+    pub autostart: Option<bool>,
+    pub allow_incompatible_mods: Option<bool>,
+    pub admins: Option<Vec<String>>,
+    pub server_settings: Option<PathBuf>,
+    /// Only used when creating the world
+    pub map_gen_settings: Option<PathBuf>,
+    /// Only used when creating the world
+    pub map_settings: Option<PathBuf>,
+    /// Mod name to either an exact version or "latest"
+    pub mods: Option<BTreeMap<String, String>>,
+}
+impl WorldManifest {
+    fn factorio(&self) -> Result<Option<VersionReq>, InvalidManifest> {
+        self.factorio
+            .as_ref()
+            .map(|s| VersionReq::from_str(s).map_err(InvalidManifest))
+            .transpose()
+    }
+
+    fn autoupdate(&self) -> Result<Option<AutoUpdate>, InvalidManifest> {
+        self.autoupdate
+            .as_ref()
+            .map(|s| {
+                AutoUpdate::from_str(s)
+                    .map_err(|_| InvalidManifest(format!("Invalid autoupdate setting {:?}", s)))
+            })
+            .transpose()
+    }
+
+    /// Mods split into (unpinned, pinned)
+    fn mods(&self) -> Result<(Vec<String>, BTreeMap<String, Version>), InvalidManifest> {
+        let mut latest = Vec::new();
+        let mut pinned = BTreeMap::new();
+        for (name, version) in self.mods.iter().flatten() {
+            if version == LATEST {
+                latest.push(name.clone());
+            } else {
+                let version = Version::try_from_str(version).map_err(|_| {
+                    InvalidManifest(format!(
+                        "Invalid version {:?} for mod {:?}, expected {:?} or major.minor.patch",
+                        version, name, LATEST
+                    ))
+                })?;
+                pinned.insert(name.clone(), version);
+            }
+        }
+        Ok((latest, pinned))
+    }
+}
+
+impl Manifest {
+    /// Loads manifest, resolving relative paths against its location
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let mut manifest: Self =
+            toml::from_str(&content).map_err(|e| InvalidManifest(e.to_string()))?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for world in manifest.worlds.values_mut() {
+            let files = [
+                &mut world.server_settings,
+                &mut world.map_gen_settings,
+                &mut world.map_settings,
+            ];
+            for file in IntoIterator::into_iter(files).flatten() {
+                *file = base.join(file.as_path());
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Renames a world in a manifest file by rewriting its table headers in place,
+    /// so comments and formatting are kept. Returns whether the file changed.
+    pub fn rename_world(
+        path: &Path, old: &str, new: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let renamed = rename_world_tables(&content, old, new);
+        if renamed == content {
+            return Ok(false);
+        }
+        fs::write(path, renamed)?;
+        Ok(true)
+    }
+
+    /// Compares the manifest against existing worlds
+    pub fn plan(&self) -> Result<Vec<Action>, Box<dyn std::error::Error>> {
+        let mut actions = Vec::new();
+        for (name, world) in &self.worlds {
+            match Server::get(name.clone()) {
+                Ok(server) => plan_existing(&mut actions, name, world, &server)?,
+                Err(error) if error.is::<NoSuchWorld>() => plan_new(&mut actions, name, world)?,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(actions)
+    }
+}
+
+fn plan_new(
+    actions: &mut Vec<Action>, name: &str, world: &WorldManifest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = &world.server_settings {
+        ServerSettings::load(path)?;
+    }
+
+    let mut meta = MetaConfig::default();
+    meta.apply_update(MetaConfigUpdate {
+        factorio: world.factorio()?,
+        autoupdate: world.autoupdate()?,
+        autoupdate_interval_minutes: world.autoupdate_interval_minutes,
+        use_server_whitelist: world.use_server_whitelist,
+        port: world.port,
+        bind: world.bind,
+        autostart: world.autostart,
+        allow_incompatible_mods: world.allow_incompatible_mods,
+    });
+
+    let (latest, pinned) = world.mods()?;
+    actions.push(Action::Create {
+        name: name.to_owned(),
+        config: CreateConfig {
+            map_gen_settings: world.map_gen_settings.clone(),
+            map_settings: world.map_settings.clone(),
+            import: ImportConfig {
+                server_settings: world.server_settings.clone(),
+                add_admin: world.admins.clone().unwrap_or_default(),
+                ..Default::default()
+            },
+            meta,
+        },
+        mods: latest,
+        pinned_mods: pinned,
+    });
+    Ok(())
+}
+
+fn plan_existing(
+    actions: &mut Vec<Action>, name: &str, world: &WorldManifest, server: &Server,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let current = &server.info.config;
+    let mut changes = Vec::new();
+
+    macro_rules! diff {
+        ($field:ident, $value:expr) => {{
+            match $value {
+                Some(v) if v != current.$field => {
+                    changes.push(format!(
+                        "{}: {:?} -> {:?}",
+                        stringify!($field),
+                        current.$field,
+                        v
+                    ));
+                    Some(v)
+                },
+                _ => None,
+            }
+        }};
+    }
+
+    let meta = MetaConfigUpdate {
+        factorio: diff!(factorio, world.factorio()?),
+        autoupdate: diff!(autoupdate, world.autoupdate()?),
+        autoupdate_interval_minutes: diff!(
+            autoupdate_interval_minutes,
+            world.autoupdate_interval_minutes
+        ),
+        use_server_whitelist: diff!(use_server_whitelist, world.use_server_whitelist),
+        port: diff!(port, world.port.map(Some)).flatten(),
+        bind: diff!(bind, world.bind.map(Some)).flatten(),
+        autostart: diff!(autostart, world.autostart),
+        allow_incompatible_mods: diff!(allow_incompatible_mods, world.allow_incompatible_mods),
+    };
+
+    let mut import = ImportConfig::default();
+    if let Some(path) = &world.server_settings {
+        if ServerSettings::load(path)? != server.server_settings()? {
+            changes.push(format!("server settings from {:?}", path));
+            import.server_settings = Some(path.clone());
+        }
+    }
+
+    if !changes.is_empty() {
+        actions.push(Action::Edit {
+            name: name.to_owned(),
+            import,
+            meta,
+            changes,
+        });
+    }
+
+    if let Some(admins) = &world.admins {
+        let current = server.player_list(PlayerList::Admins)?;
+        let add: Vec<String> = admins
+            .iter()
+            .filter(|a| !current.iter().any(|c| player_lists::same_player(a, c)))
+            .cloned()
+            .collect();
+        let remove: Vec<String> = current
+            .iter()
+            .filter(|c| !admins.iter().any(|a| player_lists::same_player(a, c)))
+            .cloned()
+            .collect();
+        if !add.is_empty() {
+            actions.push(Action::AddAdmins(name.to_owned(), add));
+        }
+        if !remove.is_empty() {
+            actions.push(Action::RemoveAdmins(name.to_owned(), remove));
+        }
+    }
+
+    if world.mods.is_some() {
+        let (latest, pinned) = world.mods()?;
+        // Local mods are managed with `add-mod --file/--dir` only
+        let installed: BTreeMap<String, Version> = server
+            .mods()
+            .into_iter()
+            .filter(|m| !server.info.local_mods.contains_key(&m.name))
+            .map(|m| (m.name, m.version))
+            .collect();
+
+        let add: Vec<String> = latest
+            .iter()
+            .filter(|m| !installed.contains_key(*m))
+            .cloned()
+            .collect();
+        if !add.is_empty() {
+            actions.push(Action::AddMods(name.to_owned(), add));
+        }
+
+        for m in latest.iter().filter(|m| installed.contains_key(*m)) {
+            if server.info.pinned_mods.contains_key(m) {
+                actions.push(Action::UnpinMod(name.to_owned(), m.clone()));
+            }
+        }
+
+        for (m, version) in &pinned {
+            if installed.get(m) != Some(version) || !server.info.pinned_mods.contains_key(m) {
+                actions.push(Action::PinMod(name.to_owned(), m.clone(), *version));
+            }
+        }
+
+        let wanted: BTreeSet<&String> = latest.iter().chain(pinned.keys()).collect();
+        let remove: Vec<String> = installed
+            .keys()
+            .filter(|m| !wanted.contains(m))
+            .cloned()
+            .collect();
+        if !remove.is_empty() {
+            actions.push(Action::RemoveMods(name.to_owned(), remove));
+        }
+    }
+
+    Ok(())
+}
+
+/// Single step required to reach the state described in the manifest
+#[derive(Debug)]
+pub enum Action {
+    Create {
+        name: String,
+        config: CreateConfig,
+        mods: Vec<String>,
+        pinned_mods: BTreeMap<String, Version>,
+    },
+    Edit {
+        name: String,
+        import: ImportConfig,
+        meta: MetaConfigUpdate,
+        /// Human-readable descriptions of the changes
+        changes: Vec<String>,
+    },
+    AddAdmins(String, Vec<String>),
+    RemoveAdmins(String, Vec<String>),
+    AddMods(String, Vec<String>),
+    PinMod(String, String, Version),
+    UnpinMod(String, String),
+    RemoveMods(String, Vec<String>),
+}
+impl Action {
+    pub fn apply(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Self::Create {
+                name,
+                config,
+                mods,
+                pinned_mods,
+            } => {
+                Server::create_with_mods(name, config, mods, pinned_mods, None, None)?;
+            },
+            Self::Edit {
+                name, import, meta, ..
+            } => Server::get(name)?.update_config(import, meta)?,
+            Self::AddAdmins(name, admins) => {
+                Server::get(name)?.add_to_player_list(PlayerList::Admins, &admins)?
+            },
+            Self::RemoveAdmins(name, admins) => {
+                Server::get(name)?.remove_from_player_list(PlayerList::Admins, &admins)?
+            },
+            Self::AddMods(name, mods) => Server::get(name)?.add_mods(mods)?,
+            Self::PinMod(name, m, version) => Server::get(name)?.pin_mod(&m, version)?,
+            Self::UnpinMod(name, m) => {
+                let mut server = Server::get(name)?;
+                server.unpin_mod(&m);
+                server.add_mods(vec![m])?;
+            },
+            Self::RemoveMods(name, mods) => Server::get(name)?.remove_mods(mods)?,
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Create {
+                name,
+                mods,
+                pinned_mods,
+                ..
+            } => {
+                write!(f, "+ create {}", name)?;
+                if !mods.is_empty() || !pinned_mods.is_empty() {
+                    let pinned = pinned_mods.iter().map(|(m, v)| format!("{} {}", m, v));
+                    let all: Vec<String> = mods.iter().cloned().chain(pinned).collect();
+                    write!(f, " with mods {}", all.join(", "))?;
+                }
+                Ok(())
+            },
+            Self::Edit { name, changes, .. } => {
+                write!(f, "~ edit {}: {}", name, changes.join(", "))
+            },
+            Self::AddAdmins(name, admins) => {
+                write!(f, "+ add admins to {}: {}", name, admins.join(", "))
+            },
+            Self::RemoveAdmins(name, admins) => {
+                write!(f, "- remove admins from {}: {}", name, admins.join(", "))
+            },
+            Self::AddMods(name, mods) => write!(f, "+ add mods to {}: {}", name, mods.join(", ")),
+            Self::PinMod(name, m, version) => {
+                write!(f, "~ pin mod {} of {} to {}", m, name, version)
+            },
+            Self::UnpinMod(name, m) => write!(f, "~ unpin mod {} of {}", m, name),
+            Self::RemoveMods(name, mods) => {
+                write!(f, "- remove mods from {}: {}", name, mods.join(", "))
+            },
+        }
+    }
+}
+
+/// Rewrites `[worlds.<old>]` and `[worlds.<old>.*]` headers
+fn rename_world_tables(content: &str, old: &str, new: &str) -> String {
+    let prefix = "[worlds.";
+    content
+        .split('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let rest = match trimmed.strip_prefix(prefix) {
+                Some(rest) => rest,
+                None => return line.to_owned(),
+            };
+            let (key, len) = if let Some(quoted) = rest.strip_prefix('"') {
+                match quoted.find('"') {
+                    Some(end) => (&quoted[..end], end + 2),
+                    None => return line.to_owned(),
+                }
+            } else {
+                let end = rest.find(&['.', ']'][..]).unwrap_or(rest.len());
+                (rest[..end].trim_end(), end)
+            };
+            if key != old {
+                return line.to_owned();
+            }
+
+            let indent = &line[..line.len() - trimmed.len()];
+            format!("{}{}{}{}", indent, prefix, toml_key(new), &rest[len..])
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_owned()
+    } else {
+        format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [worlds.Example]
+            factorio = "0.18"
+            autoupdate = "forced"
+            admins = ["Admin"]
+
+            [worlds.Example.mods]
+            Krastorio2 = "latest"
+            rso-mod = "6.0.11"
+            "#,
+        )
+        .unwrap();
+
+        let world = &manifest.worlds["Example"];
+        assert_eq!(
+            world.factorio().unwrap(),
+            Some(VersionReq::Specific("0.18".to_owned()))
+        );
+        assert_eq!(world.autoupdate().unwrap(), Some(AutoUpdate::Forced));
+        assert_eq!(world.port, None);
+
+        let (latest, pinned) = world.mods().unwrap();
+        assert_eq!(latest, vec!["Krastorio2"]);
+        assert_eq!(pinned["rso-mod"], Version::try_from_str("6.0.11").unwrap());
+    }
+
+    #[test]
+    fn rename_world() {
+        let content = "# Servers\n[worlds.Old]\nport = 1\n\n[worlds.Old.mods]\n\
+                       [worlds.Older]\n[worlds.\"Old\"]\r\n";
+        assert_eq!(
+            rename_world_tables(content, "Old", "New World"),
+            "# Servers\n[worlds.\"New World\"]\nport = 1\n\n[worlds.\"New World\".mods]\n\
+             [worlds.Older]\n[worlds.\"New World\"]\r\n"
+        );
+    }
+
+    #[test]
+    fn invalid_mod_version() {
+        let world = WorldManifest {
+            mods: Some(
+                vec![("a".to_owned(), "1.0".to_owned())]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        assert!(world.mods().is_err());
+    }
+}
@@ -0,0 +1,555 @@
+//! Reads and writes `mod-settings.dat`, stored in Factorio's binary property tree format
+
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::error::InvalidModSettings;
+use crate::save::HeaderReader;
+use crate::version::Version;
+
+/// Setting scopes in the order Factorio writes them
+pub const SCOPES: &[&str] = &["startup", "runtime-global", "runtime-per-user"];
+
+/// Dynamically typed value as serialized by Factorio
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyTree {
+    None,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<PropertyTree>),
+    Dictionary(Vec<(String, PropertyTree)>),
+    SignedInteger(i64),
+    UnsignedInteger(u64),
+}
+impl PropertyTree {
+    pub(crate) fn read<R: Read>(r: &mut HeaderReader<R>) -> io::Result<Self> {
+        let kind = r.u8()?;
+        let _any_type = r.u8()?;
+        Ok(match kind {
+            0 => Self::None,
+            1 => Self::Bool(r.u8()? != 0),
+            2 => Self::Number(r.f64()?),
+            3 => Self::String(read_string(r)?),
+            4 => {
+                let count = r.u32()?;
+                let mut items = Vec::new();
+                for _ in 0..count {
+                    let _key = read_string(r)?;
+                    items.push(Self::read(r)?);
+                }
+                Self::List(items)
+            },
+            5 => {
+                let count = r.u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    let key = read_string(r)?;
+                    entries.push((key, Self::read(r)?));
+                }
+                Self::Dictionary(entries)
+            },
+            6 => Self::SignedInteger(r.u64()? as i64),
+            7 => Self::UnsignedInteger(r.u64()?),
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown property type {}", kind),
+                ));
+            },
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let kind = match self {
+            Self::None => 0,
+            Self::Bool(_) => 1,
+            Self::Number(_) => 2,
+            Self::String(_) => 3,
+            Self::List(_) => 4,
+            Self::Dictionary(_) => 5,
+            Self::SignedInteger(_) => 6,
+            Self::UnsignedInteger(_) => 7,
+        };
+        out.extend(&[kind, 0]);
+
+        match self {
+            Self::None => {},
+            Self::Bool(b) => out.push(*b as u8),
+            Self::Number(n) => out.extend(&n.to_le_bytes()),
+            Self::String(s) => write_string(out, s),
+            Self::List(items) => {
+                out.extend(&(items.len() as u32).to_le_bytes());
+                for item in items {
+                    write_string(out, "");
+                    item.write(out);
+                }
+            },
+            Self::Dictionary(entries) => {
+                out.extend(&(entries.len() as u32).to_le_bytes());
+                for (key, value) in entries {
+                    write_string(out, key);
+                    value.write(out);
+                }
+            },
+            Self::SignedInteger(n) => out.extend(&n.to_le_bytes()),
+            Self::UnsignedInteger(n) => out.extend(&n.to_le_bytes()),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::None => Value::Null,
+            Self::Bool(b) => Value::Bool(*b),
+            Self::Number(n) => serde_json::Number::from_f64(*n).map_or(Value::Null, Value::Number),
+            Self::String(s) => Value::String(s.clone()),
+            Self::List(items) => Value::Array(items.iter().map(Self::to_json).collect()),
+            Self::Dictionary(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.to_json()))
+                    .collect(),
+            ),
+            Self::SignedInteger(n) => Value::from(*n),
+            Self::UnsignedInteger(n) => Value::from(*n),
+        }
+    }
+
+    /// Converts JSON, numbers keep the type of the corresponding value in `like`
+    /// since JSON doesn't tell integers and doubles apart
+    pub fn from_json(value: &Value, like: Option<&Self>) -> Self {
+        match value {
+            Value::Null => Self::None,
+            Value::Bool(b) => Self::Bool(*b),
+            Value::Number(n) => match (like, n.as_i64(), n.as_u64()) {
+                (Some(Self::SignedInteger(_)), Some(i), _) => Self::SignedInteger(i),
+                (Some(Self::UnsignedInteger(_)), _, Some(u)) => Self::UnsignedInteger(u),
+                _ => Self::Number(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => Self::String(s.clone()),
+            Value::Array(items) => {
+                let like = match like {
+                    Some(Self::List(items)) => items.first(),
+                    _ => None,
+                };
+                Self::List(items.iter().map(|v| Self::from_json(v, like)).collect())
+            },
+            Value::Object(entries) => Self::Dictionary(
+                entries
+                    .iter()
+                    .map(|(key, v)| {
+                        (
+                            key.clone(),
+                            Self::from_json(v, like.and_then(|l| l.get(key))),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Value of a dictionary entry
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Dictionary(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Sets a dictionary entry, keeping its position if it exists
+    fn insert(&mut self, key: &str, value: Self) {
+        if let Self::Dictionary(entries) = self {
+            match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => *v = value,
+                None => entries.push((key.to_owned(), value)),
+            }
+        }
+    }
+
+    /// Dictionary entry, which is created if it doesn't exist.
+    /// Panics if this is not a dictionary, `parse` makes sure that scopes and settings are.
+    fn entry(&mut self, key: &str) -> &mut Self {
+        if self.get(key).is_none() {
+            self.insert(key, Self::Dictionary(Vec::new()));
+        }
+        match self {
+            Self::Dictionary(entries) => &mut entries.iter_mut().find(|(k, _)| k == key).unwrap().1,
+            _ => panic!("Not a dictionary"),
+        }
+    }
+}
+
+/// Checks that the tree is a dictionary of scopes, each a dictionary of settings
+fn validate(tree: &PropertyTree) -> Result<(), String> {
+    let scopes = match tree {
+        PropertyTree::Dictionary(scopes) => scopes,
+        _ => return Err("expected a dictionary of scopes".to_owned()),
+    };
+    for (scope, settings) in scopes {
+        let settings = match settings {
+            PropertyTree::Dictionary(settings) => settings,
+            _ => return Err(format!("expected a dictionary of settings in {}", scope)),
+        };
+        for (name, setting) in settings {
+            if !matches!(setting, PropertyTree::Dictionary(_)) {
+                return Err(format!("expected a dictionary for setting {}", name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Strings start with a flag for the empty string
+fn read_string<R: Read>(r: &mut HeaderReader<R>) -> io::Result<String> {
+    if r.u8()? != 0 {
+        Ok(String::new())
+    } else {
+        r.string()
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    if s.is_empty() {
+        out.push(1);
+        return;
+    }
+    out.push(0);
+    if s.len() < 0xff {
+        out.push(s.len() as u8);
+    } else {
+        out.push(0xff);
+        out.extend(&(s.len() as u32).to_le_bytes());
+    }
+    out.extend(s.as_bytes());
+}
+
+/// Contents of `mod-settings.dat`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModSettings {
+    /// Factorio version that wrote the file
+    pub version: Version,
+    pub build: u16,
+    /// Dictionary of scopes, each mapping setting names to `{"value": ...}`
+    pub tree: PropertyTree,
+}
+impl ModSettings {
+    /// Settings without any values, as if written by the given version
+    pub fn new(version: Version) -> Self {
+        Self {
+            version,
+            build: 0,
+            tree: PropertyTree::Dictionary(
+                SCOPES
+                    .iter()
+                    .map(|scope| (scope.to_string(), PropertyTree::Dictionary(Vec::new())))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, InvalidModSettings> {
+        let invalid = |e: io::Error| InvalidModSettings(format!("{:?}: {}", path, e));
+        let file = File::open(path).map_err(invalid)?;
+        Self::parse(BufReader::new(file)).map_err(invalid)
+    }
+
+    /// Loads `path`, or empty settings if the file doesn't exist
+    pub fn load_or_new(path: &Path, version: Version) -> Result<Self, InvalidModSettings> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::new(version))
+        }
+    }
+
+    /// Loads a `.json` export, or `mod-settings.dat` otherwise.
+    /// JSON numbers keep the types of `base`, which also provides the file version.
+    pub fn import(path: &Path, base: &Self) -> Result<Self, InvalidModSettings> {
+        if path.extension() != Some("json".as_ref()) {
+            return Self::load(path);
+        }
+
+        let invalid = |e: String| InvalidModSettings(format!("{:?}: {}", path, e));
+        let data = fs::read(path).map_err(|e| invalid(e.to_string()))?;
+        let json: Value = serde_json::from_slice(&data).map_err(|e| invalid(e.to_string()))?;
+        Self::from_json(&json, base).map_err(invalid)
+    }
+
+    pub fn parse(r: impl Read) -> io::Result<Self> {
+        let mut r = HeaderReader(r);
+        let version = Version::new(r.u16()?.into(), r.u16()?.into(), r.u16()?.into());
+        let build = r.u16()?;
+        if version >= Version::new(0, 17, 0) {
+            let _unknown = r.u8()?;
+        }
+        let tree = PropertyTree::read(&mut r)?;
+        validate(&tree).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut trailing = [0; 1];
+        if r.0.read(&mut trailing)? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected data after settings",
+            ));
+        }
+        Ok(Self {
+            version,
+            build,
+            tree,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (major, minor, patch) = self.version.parts();
+        for part in &[major, minor, patch] {
+            out.extend(&(*part as u16).to_le_bytes());
+        }
+        out.extend(&self.build.to_le_bytes());
+        if self.version >= Version::new(0, 17, 0) {
+            out.push(0);
+        }
+        self.tree.write(&mut out);
+        out
+    }
+
+    pub fn store(&self, path: &Path) {
+        fs::write(path, self.to_bytes()).expect("Could not write mod-settings.dat");
+    }
+
+    /// All settings as `(scope, name, value)`
+    pub fn values(&self) -> Vec<(&str, &str, &PropertyTree)> {
+        let mut values = Vec::new();
+        if let PropertyTree::Dictionary(scopes) = &self.tree {
+            for (scope, settings) in scopes {
+                if let PropertyTree::Dictionary(settings) = settings {
+                    for (name, setting) in settings {
+                        if let Some(value) = setting.get("value") {
+                            values.push((scope.as_str(), name.as_str(), value));
+                        }
+                    }
+                }
+            }
+        }
+        values
+    }
+
+    /// Value and scope of a setting
+    pub fn get(&self, name: &str) -> Option<(&str, &PropertyTree)> {
+        self.values()
+            .into_iter()
+            .find(|(_, n, _)| *n == name)
+            .map(|(scope, _, value)| (scope, value))
+    }
+
+    /// Changes a setting, parsing the value as JSON if possible and as a string otherwise.
+    /// New settings are added to `scope`, which defaults to startup,
+    /// integers are rejected for them like in `from_json`.
+    /// The type of existing settings can't be changed.
+    pub fn set(
+        &mut self, name: &str, value: &str, scope: Option<&str>,
+    ) -> Result<(), InvalidModSettings> {
+        let existing = self.get(name).map(|(s, v)| (s.to_owned(), v.clone()));
+        let scope = match (scope, &existing) {
+            (Some(scope), _) if !SCOPES.contains(&scope) => {
+                return Err(InvalidModSettings(format!(
+                    "Unknown scope {}, expected one of {}",
+                    scope,
+                    SCOPES.join(", ")
+                )));
+            },
+            (Some(scope), _) => scope.to_owned(),
+            (None, Some((scope, _))) => scope.clone(),
+            (None, None) => SCOPES[0].to_owned(),
+        };
+        let like = existing.as_ref().map(|(_, v)| v);
+
+        let json = match like {
+            Some(PropertyTree::String(_)) => Value::String(value.to_owned()),
+            _ => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned())),
+        };
+        if like.is_none() && (json.is_i64() || json.is_u64()) {
+            return Err(InvalidModSettings(format!(
+                "Type of {} is unknown, {} could be an integer or a double. \
+                 Import the settings from a mod-settings.dat first, or write the value as a double",
+                name, value
+            )));
+        }
+        let new = PropertyTree::from_json(&json, like);
+        if let Some(like) = like {
+            // Integers that don't fit the existing type are converted to doubles and rejected
+            if std::mem::discriminant(&new) != std::mem::discriminant(like) {
+                return Err(InvalidModSettings(format!(
+                    "Invalid value {:?} for {}, expected a value like {}",
+                    value,
+                    name,
+                    like.to_json()
+                )));
+            }
+        }
+
+        if let Some((old_scope, _)) = &existing {
+            if *old_scope != scope {
+                if let PropertyTree::Dictionary(settings) = self.tree.entry(old_scope) {
+                    settings.retain(|(n, _)| n != name);
+                }
+            }
+        }
+        self.tree.entry(&scope).entry(name).insert("value", new);
+        Ok(())
+    }
+
+    /// Settings as `{"scope": {"name": value}}`
+    pub fn to_json(&self) -> Value {
+        let mut scopes = Map::new();
+        for scope in SCOPES {
+            scopes.insert(scope.to_string(), Value::Object(Map::new()));
+        }
+        for (scope, name, value) in self.values() {
+            if let Some(Value::Object(settings)) = scopes.get_mut(scope) {
+                settings.insert(name.to_owned(), value.to_json());
+            }
+        }
+        Value::Object(scopes)
+    }
+
+    /// Reverses `to_json`, numbers keep the types of settings in `base`.
+    /// Integers are rejected for settings missing from `base`.
+    pub fn from_json(json: &Value, base: &Self) -> Result<Self, String> {
+        let scopes = json.as_object().ok_or("expected an object of scopes")?;
+        let mut settings = Self::new(base.version);
+        settings.build = base.build;
+        for (scope, values) in scopes {
+            if !SCOPES.contains(&scope.as_str()) {
+                return Err(format!("unknown scope {}", scope));
+            }
+            let values = values
+                .as_object()
+                .ok_or_else(|| format!("expected an object of settings in {}", scope))?;
+            for (name, value) in values {
+                let like = base.get(name).map(|(_, v)| v);
+                if like.is_none() && (value.is_i64() || value.is_u64()) {
+                    return Err(format!(
+                        "type of {} is unknown, JSON can't tell integers from doubles. \
+                         Import the settings from a mod-settings.dat first, \
+                         or write the value as a double",
+                        name
+                    ));
+                }
+                let value = PropertyTree::from_json(value, like);
+                settings
+                    .tree
+                    .entry(scope)
+                    .entry(name)
+                    .insert("value", value);
+            }
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn example() -> ModSettings {
+        let mut settings = ModSettings::new(Version::new(0, 18, 27));
+        settings.set("rso-starting-radius", "3.5", None).unwrap();
+        settings
+            .set("bobmods-logistics-beltoverhaul", "true", None)
+            .unwrap();
+        settings
+            .set("rso-map-style", "default", Some("runtime-global"))
+            .unwrap();
+        settings
+    }
+
+    #[test]
+    fn round_trip() {
+        let settings = example();
+        let bytes = settings.to_bytes();
+        assert_eq!(&bytes[..8], &[0, 0, 18, 0, 27, 0, 0, 0]);
+        assert_eq!(ModSettings::parse(bytes.as_slice()).unwrap(), settings);
+
+        let json = settings.to_json();
+        assert_eq!(json["startup"]["rso-starting-radius"], 3.5);
+        assert_eq!(json["runtime-global"]["rso-map-style"], "default");
+        // JSON objects are sorted, so only the values are compared
+        let from_json = ModSettings::from_json(&json, &settings).unwrap();
+        assert_eq!(from_json.to_json(), json);
+        assert_eq!(from_json.version, settings.version);
+
+        assert!(ModSettings::parse(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn invalid_structure() {
+        let mut settings = example();
+        settings.tree = PropertyTree::Dictionary(vec![(
+            "startup".to_owned(),
+            PropertyTree::String("not settings".to_owned()),
+        )]);
+        assert!(ModSettings::parse(settings.to_bytes().as_slice()).is_err());
+
+        settings.tree = PropertyTree::List(Vec::new());
+        assert!(ModSettings::parse(settings.to_bytes().as_slice()).is_err());
+    }
+
+    #[test]
+    fn json_integers_need_types() {
+        let mut base = example();
+        let json = serde_json::json!({ "startup": { "count": 5 } });
+        assert!(ModSettings::from_json(&json, &base).is_err());
+
+        base.tree
+            .entry("startup")
+            .entry("count")
+            .insert("value", PropertyTree::SignedInteger(1));
+        let settings = ModSettings::from_json(&json, &base).unwrap();
+        assert_eq!(
+            settings.get("count"),
+            Some(("startup", &PropertyTree::SignedInteger(5)))
+        );
+    }
+
+    #[test]
+    fn set_keeps_types() {
+        let mut settings = example();
+        settings
+            .tree
+            .entry("startup")
+            .entry("count")
+            .insert("value", PropertyTree::SignedInteger(1));
+
+        settings.set("count", "5", None).unwrap();
+        assert_eq!(
+            settings.get("count"),
+            Some(("startup", &PropertyTree::SignedInteger(5)))
+        );
+        settings.set("rso-map-style", "true", None).unwrap();
+        assert_eq!(
+            settings.get("rso-map-style"),
+            Some(("runtime-global", &PropertyTree::String("true".to_owned())))
+        );
+
+        assert!(settings.set("count", "1.5", None).is_err());
+        assert!(settings.set("new-count", "5", None).is_err());
+        settings.set("new-count", "5.0", None).unwrap();
+        assert_eq!(
+            settings.get("new-count"),
+            Some(("startup", &PropertyTree::Number(5.0)))
+        );
+        assert!(
+            settings
+                .set("bobmods-logistics-beltoverhaul", "3", None)
+                .is_err()
+        );
+        assert!(
+            settings
+                .set("new-setting", "1", Some("no-such-scope"))
+                .is_err()
+        );
+    }
+}
@@ -1,22 +1,64 @@
+use lazy_static::lazy_static;
 use reqwest::{
-    blocking::{Client, ClientBuilder},
+    blocking::Client,
     header, StatusCode,
 };
+use crossbeam_channel::unbounded;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::copy;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
 
 use crate::config::{LoginCredentials, TokenCredentials};
 use crate::dirs;
 use crate::error::{
-    InternalDataModified, LoginFailed, NoMatchingModVersions, NoSuchMod, NotLoggedIn,
+    InternalDataModified, InvalidMod, LoginFailed, NoMatchingModVersions, NoSuchMod,
+    NoSuchModVersion, NotLoggedIn, SendError, unsend,
 };
+use crate::http;
+use crate::progress::Progress;
+use crate::settings;
 use crate::version::{EitherVersion, Version};
 
 const INVALID_DATA: &str = "Invalid response from factorio API";
 
+/// Concurrent requests when resolving mod versions
+const RESOLVE_WORKERS: usize = 8;
+
+/// Concurrent mod downloads
+const DOWNLOAD_WORKERS: usize = 4;
+
+lazy_static! {
+    /// Mod files that are being downloaded, prevents concurrent downloads of the same mod
+    static ref DOWNLOADING: (Mutex<HashSet<PathBuf>>, Condvar) = Default::default();
+}
+
+/// Marks a mod file as being downloaded until dropped
+struct DownloadGuard(PathBuf);
+impl DownloadGuard {
+    /// Waits until no other thread is downloading `path`
+    fn lock(path: PathBuf) -> Self {
+        let (downloading, condvar) = &*DOWNLOADING;
+        let mut downloading = downloading.lock().unwrap();
+        while downloading.contains(&path) {
+            downloading = condvar.wait(downloading).unwrap();
+        }
+        downloading.insert(path.clone());
+        Self(path)
+    }
+}
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        let (downloading, condvar) = &*DOWNLOADING;
+        downloading.lock().unwrap().remove(&self.0);
+        condvar.notify_all();
+    }
+}
+
 #[derive(Debug)]
 pub struct ModInfo {
     pub name: String,
@@ -63,7 +105,7 @@ impl ModDownloader {
     pub fn new() -> Result<Self, NotLoggedIn> {
         if let Some(credentials) = TokenCredentials::load() {
             Ok(Self {
-                client: ClientBuilder::new().cookie_store(true).build().unwrap(),
+                client: http::client(),
                 credentials,
             })
         } else {
@@ -75,49 +117,90 @@ impl ModDownloader {
     pub fn require(
         &self, name: &str, game_version: Version,
     ) -> Result<ModInfo, Box<dyn std::error::Error>> {
-        let (mod_info, download_link) = latest_version(&self.client, name, game_version)?;
+        let (mod_info, download_link) =
+            latest_version(&self.client, name, game_version).map_err(unsend)?;
+        self.download_if_missing(mod_info, &download_link, &Progress::new(1))
+            .map_err(unsend)
+    }
+
+    /// Downloads an exact version
+    pub fn require_exact(
+        &self, name: &str, version: Version,
+    ) -> Result<ModInfo, Box<dyn std::error::Error>> {
+        let (mod_info, download_link) = exact_version(&self.client, name, version).map_err(unsend)?;
+        self.download_if_missing(mod_info, &download_link, &Progress::new(1))
+            .map_err(unsend)
+    }
 
+    /// Downloads latest matching versions of several mods,
+    /// resolving versions and downloading with multiple connections at once
+    pub fn require_all(
+        &self, names: &[String], game_version: Version,
+    ) -> Result<Vec<ModInfo>, Box<dyn std::error::Error>> {
+        let resolved = parallel(names.iter().collect(), RESOLVE_WORKERS, |name| {
+            latest_version(&self.client, name, game_version)
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(unsend)?;
+
+        let missing = resolved.iter().filter(|(m, _)| !m.path().exists()).count();
+        let progress = Progress::new(missing);
+        let result = parallel(resolved, DOWNLOAD_WORKERS, |(mod_info, url)| {
+            self.download_if_missing(mod_info, &url, &progress)
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>();
+        progress.finish();
+        result.map_err(unsend)
+    }
+
+    fn download_if_missing(
+        &self, mod_info: ModInfo, url: &str, progress: &Progress,
+    ) -> Result<ModInfo, SendError> {
+        let _guard = DownloadGuard::lock(mod_info.path());
         dirs::create_mods_dir();
         if mod_info.path().exists() {
             log::trace!("Mod {:?} already downloaded", mod_info);
             return Ok(mod_info);
         }
 
-        self.download_mod(&mod_info, &download_link)?;
+        self.download_mod(&mod_info, url, progress)?;
         Ok(mod_info)
     }
 
     fn download_mod(
-        &self, mod_info: &ModInfo, url: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        &self, mod_info: &ModInfo, url: &str, progress: &Progress,
+    ) -> Result<(), SendError> {
         log::trace!("Downloading mod {:?}", mod_info);
 
-        let mut r = self
-            .client
-            .get(&format!("https://mods.factorio.com{}", url))
-            .query(&json!({
-                "username": self.credentials.username.clone(),
-                "token": self.credentials.token.plaintext.clone()
-            }))
-            .send()?;
-
-        if r.headers()[header::CONTENT_TYPE]
-            .to_str()
-            .unwrap()
-            .starts_with("text/html")
-        {
-            return Err(Box::new(NotLoggedIn));
-        }
-
-        let mut f = File::create(&mod_info.path())?;
-        copy(&mut r, &mut f)?;
-        Ok(())
+        http::download_file(
+            &self.client,
+            &settings::urls().mods(url),
+            &mod_info.path(),
+            progress,
+            |request| {
+                request.query(&json!({
+                    "username": self.credentials.username.clone(),
+                    "token": self.credentials.token.plaintext.clone()
+                }))
+            },
+            |response| {
+                // Without valid credentials the portal responds with its login page
+                let html = response
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|t| t.to_str().ok())
+                    .map_or(false, |t| t.starts_with("text/html"));
+                if html { Err(Box::new(NotLoggedIn)) } else { Ok(()) }
+            },
+        )
     }
 
-    pub fn login(&self, credentials: LoginCredentials) -> Result<(), Box<dyn std::error::Error>> {
-        let resp = self
-            .client
-            .post("https://auth.factorio.com/api-login")
+    /// Requests a token and stores it, no previous login required
+    pub fn login(credentials: LoginCredentials) -> Result<(), Box<dyn std::error::Error>> {
+        let resp = http::client()
+            .post(&settings::urls().auth("/api-login"))
             .form(&json!({
                 "username": credentials.username.expect("Username required"),
                 "password": credentials.password.expect("Password required").plaintext,
@@ -142,13 +225,88 @@ impl ModDownloader {
     }
 }
 
-mod api {
+/// Read-only access to the mod portal, no login required
+pub struct ModPortal {
+    client: Client,
+}
+impl Default for ModPortal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ModPortal {
+    pub fn new() -> Self {
+        Self {
+            client: http::client(),
+        }
+    }
+
+    /// Mods with all words of the query in their name, title or summary, most downloaded first.
+    /// The portal has no search API, so the full mod list is filtered locally.
+    pub fn search(&self, query: &str) -> Result<Vec<api::Mod>, Box<dyn std::error::Error>> {
+        log::info!("Fetching mod list");
+        let list: api::ModList = self
+            .client
+            .get(&settings::urls().mods("/api/mods"))
+            .query(&[("page_size", "max")])
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let mut results: Vec<api::Mod> = list
+            .results
+            .into_iter()
+            .filter(|m| {
+                let text = format!("{} {} {}", m.name, m.title, m.summary).to_lowercase();
+                words.iter().all(|w| text.contains(w))
+            })
+            .collect();
+        results.sort_by_key(|m| std::cmp::Reverse(m.downloads_count));
+        Ok(results)
+    }
+
+    /// Full details of a mod, including changelog and dependencies of each release
+    pub fn info(&self, name: &str) -> Result<api::Mod, Box<dyn std::error::Error>> {
+        fetch_mod(&self.client, name, true).map_err(unsend)
+    }
+
+    /// All releases of a mod, oldest first
+    pub fn releases(&self, name: &str) -> Result<Vec<api::ModRelease>, Box<dyn std::error::Error>> {
+        Ok(fetch_mod(&self.client, name, false).map_err(unsend)?.releases)
+    }
+}
+
+pub mod api {
     use serde::Deserialize;
 
+    use crate::version::{EitherVersion, Version};
+
+    #[derive(Debug, Deserialize)]
+    pub struct ModList {
+        pub results: Vec<Mod>,
+    }
+
+    /// Mod details, fields that are only returned by some endpoints are defaulted
     #[derive(Debug, Deserialize)]
     pub struct Mod {
         pub name: String,
+        #[serde(default)]
+        pub title: String,
+        #[serde(default)]
+        pub owner: String,
+        #[serde(default)]
+        pub summary: String,
+        #[serde(default)]
+        pub downloads_count: u64,
+        #[serde(default)]
         pub releases: Vec<ModRelease>,
+        /// Only set in mod lists, which don't include `releases`
+        #[serde(default)]
+        pub latest_release: Option<ModRelease>,
+        /// Contents of `changelog.txt`, only included in full mod details
+        #[serde(default)]
+        pub changelog: String,
     }
 
     #[derive(Debug, Deserialize)]
@@ -157,42 +315,82 @@ mod api {
         pub version: String,
         pub info_json: ModReleaseInfoJson,
     }
+    impl ModRelease {
+        /// Whether this release can be used with a Factorio version
+        pub fn supports(&self, game_version: Version) -> bool {
+            EitherVersion::try_from_str(&self.info_json.factorio_version)
+                .expect(super::INVALID_DATA)
+                .includes(game_version)
+        }
+    }
     #[derive(Debug, Deserialize)]
     pub struct ModReleaseInfoJson {
         pub factorio_version: String,
+        /// Only included in full mod details
+        #[serde(default)]
+        pub dependencies: Vec<String>,
     }
 }
 
-/// Resolves latest matching version
-fn latest_version(
-    client: &Client, name: &str, game_version: Version,
-) -> Result<(ModInfo, String), Box<dyn std::error::Error>> {
-    let error = Box::new(NoMatchingModVersions(name.to_owned(), game_version));
-
+/// Fetches mod details from the portal, `full` includes dependencies
+fn fetch_mod(client: &Client, name: &str, full: bool) -> Result<api::Mod, SendError> {
     log::trace!("Fetching version information for mod {:?}", name);
 
-    let resp = client
-        .get(&format!("https://mods.factorio.com/api/mods/{}", name))
-        .send()?;
+    let mut url = settings::urls().mods(&format!("/api/mods/{}", name));
+    if full {
+        url.push_str("/full");
+    }
+    let resp = client.get(&url).send()?;
 
     if resp.status() == StatusCode::NOT_FOUND {
         return Err(Box::new(NoSuchMod(name.to_owned())));
     }
 
-    resp.json::<api::Mod>()?
+    Ok(resp.json::<api::Mod>()?)
+}
+
+/// Latest release compatible with a Factorio version
+pub fn latest_compatible(
+    releases: &[api::ModRelease], game_version: Version,
+) -> Option<&api::ModRelease> {
+    releases.iter().filter(|r| r.supports(game_version)).last()
+}
+
+/// Resolves latest matching version
+fn latest_version(
+    client: &Client, name: &str, game_version: Version,
+) -> Result<(ModInfo, String), SendError> {
+    let error = Box::new(NoMatchingModVersions(name.to_owned(), game_version));
+
+    let details = fetch_mod(client, name, false)?;
+    latest_compatible(&details.releases, game_version)
+        .map(|r| {
+            (
+                ModInfo {
+                    name: name.to_owned(),
+                    version: Version::try_from_str(&r.version).expect(INVALID_DATA),
+                },
+                r.download_url.clone(),
+            )
+        })
+        .ok_or(error)
+}
+
+/// Resolves an exact version
+fn exact_version(
+    client: &Client, name: &str, version: Version,
+) -> Result<(ModInfo, String), SendError> {
+    let error = Box::new(NoSuchModVersion(name.to_owned(), version));
+
+    fetch_mod(client, name, false)?
         .releases
         .iter()
-        .filter(|r| {
-            EitherVersion::try_from_str(&r.info_json.factorio_version)
-                .expect(INVALID_DATA)
-                .includes(game_version)
-        })
-        .last()
+        .find(|r| Version::try_from_str(&r.version).expect(INVALID_DATA) == version)
         .map(|r| {
             (
                 ModInfo {
                     name: name.to_owned(),
-                    version: Version::try_from_str(&r.version).expect(INVALID_DATA),
+                    version,
                 },
                 r.download_url.clone(),
             )
@@ -200,6 +398,35 @@ fn latest_version(
         .ok_or(error)
 }
 
+/// Applies `f` to all items using up to `workers` threads, keeping the order of results
+fn parallel<T: Send, R: Send>(items: Vec<T>, workers: usize, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let count = items.len();
+    let (tx, rx) = unbounded();
+    for item in items.into_iter().enumerate() {
+        tx.send(item).unwrap();
+    }
+    drop(tx);
+
+    let results = Mutex::new((0..count).map(|_| None).collect::<Vec<Option<R>>>());
+    thread::scope(|s| {
+        for _ in 0..workers.min(count) {
+            s.spawn(|| {
+                for (i, item) in rx.iter() {
+                    let result = f(item);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("Worker thread crashed"))
+        .collect()
+}
+
 #[derive(Deserialize)]
 pub struct ModListJson {
     mods: Vec<ModListJsonMod>,
@@ -225,3 +452,116 @@ pub fn load_mod_list_json(path: &Path) -> Result<Vec<String>, Box<dyn std::error
         })
         .collect())
 }
+
+/// Fields of a mod's `info.json` needed to install it
+#[derive(Debug, Deserialize)]
+pub struct ModInfoJson {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub factorio_version: Option<String>,
+}
+impl ModInfoJson {
+    /// Reads `info.json` from a mod zip or an unpacked mod directory
+    pub fn read(path: &Path) -> Result<Self, InvalidMod> {
+        let invalid = |reason: String| InvalidMod(format!("{:?}: {}", path, reason));
+
+        let data = if path.is_dir() {
+            fs::read(path.join("info.json")).map_err(|e| invalid(e.to_string()))?
+        } else {
+            let file = File::open(path).map_err(|e| invalid(e.to_string()))?;
+            let mut archive = zip::ZipArchive::new(file).map_err(|e| invalid(e.to_string()))?;
+            // Mod zips contain a single top level directory
+            let index = (0..archive.len())
+                .find(|&i| {
+                    archive.by_index(i).map_or(false, |f| {
+                        let entry = Path::new(f.name());
+                        entry.components().count() == 2 && entry.ends_with("info.json")
+                    })
+                })
+                .ok_or_else(|| invalid("no info.json found".to_owned()))?;
+            let mut data = Vec::new();
+            archive
+                .by_index(index)
+                .and_then(|mut f| Ok(f.read_to_end(&mut data)?))
+                .map_err(|e| invalid(e.to_string()))?;
+            data
+        };
+
+        serde_json::from_slice(&data).map_err(|e| invalid(format!("info.json: {}", e)))
+    }
+
+    /// Checks that the mod can be installed for a Factorio version
+    pub fn validate(&self, path: &Path, game_version: Version) -> Result<ModInfo, InvalidMod> {
+        let invalid = |reason: String| InvalidMod(format!("{:?}: {}", path, reason));
+
+        let version = Version::try_from_str(&self.version)
+            .map_err(|_| invalid(format!("invalid version {:?}", self.version)))?;
+
+        // Zips are looked up by Factorio as `name_version.zip`
+        if path.is_file() {
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if let Some(from_name) = ModInfo::from_file_name(file_name) {
+                if from_name.name != self.name || from_name.version != version {
+                    return Err(invalid(format!(
+                        "file name does not match info.json ({} {})",
+                        self.name, version
+                    )));
+                }
+            }
+        }
+
+        let factorio_version = self.factorio_version.as_deref().unwrap_or("0.12");
+        let compatible = EitherVersion::try_from_str(factorio_version)
+            .map_err(|_| invalid(format!("invalid factorio_version {:?}", factorio_version)))?
+            .includes(game_version);
+        if !compatible {
+            return Err(invalid(format!(
+                "{} {} is for Factorio {}, the world uses {}",
+                self.name, version, factorio_version, game_version
+            )));
+        }
+
+        Ok(ModInfo {
+            name: self.name.clone(),
+            version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_info_json() {
+        let info = ModInfoJson {
+            name: "private-mod".to_owned(),
+            version: "1.2.0".to_owned(),
+            factorio_version: Some("0.18".to_owned()),
+        };
+        let path = Path::new("private-mod");
+
+        let mod_info = info.validate(path, Version::new(0, 18, 27)).unwrap();
+        assert_eq!(mod_info.name, "private-mod");
+        assert_eq!(mod_info.version, Version::new(1, 2, 0));
+        assert!(info.validate(path, Version::new(0, 17, 79)).is_err());
+
+        let info = ModInfoJson {
+            version: "latest".to_owned(),
+            ..info
+        };
+        assert!(info.validate(path, Version::new(0, 18, 27)).is_err());
+    }
+
+    #[test]
+    fn parallel_keeps_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let results = parallel(items, 4, |i| {
+            thread::sleep(std::time::Duration::from_millis(20 - i));
+            i * 2
+        });
+        assert_eq!(results, (0..20).map(|i| i * 2).collect::<Vec<_>>());
+        assert!(parallel(Vec::<u64>::new(), 4, |i| i).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Admin, whitelist and ban list files of a server
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// Lists that consist of plain player names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerList {
+    Admins,
+    Whitelist,
+}
+impl PlayerList {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Admins => "server-adminlist.json",
+            Self::Whitelist => "server-whitelist.json",
+        }
+    }
+
+    /// Console command applying addition to a running server
+    pub fn add_command(self, player: &str) -> String {
+        match self {
+            Self::Admins => format!("/promote {}", player),
+            Self::Whitelist => format!("/whitelist add {}", player),
+        }
+    }
+
+    /// Console command applying removal to a running server
+    pub fn remove_command(self, player: &str) -> String {
+        match self {
+            Self::Admins => format!("/demote {}", player),
+            Self::Whitelist => format!("/whitelist remove {}", player),
+        }
+    }
+}
+
+/// Loads a list of player names, missing file is treated as an empty list
+pub fn load_names(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+pub fn store_names(path: &Path, names: &[String]) {
+    fs::write(path, serde_json::to_string_pretty(names).unwrap()).expect("Could not write file");
+}
+
+/// Adds names not already on the list, returns the ones that were added
+pub fn add_names(list: &mut Vec<String>, names: &[String]) -> Vec<String> {
+    let mut added = Vec::new();
+    for name in names {
+        if !list.iter().any(|n| same_player(n, name)) {
+            list.push(name.clone());
+            added.push(name.clone());
+        }
+    }
+    added
+}
+
+/// Removes names from the list, returns the ones that were removed
+pub fn remove_names(list: &mut Vec<String>, names: &[String]) -> Vec<String> {
+    let mut removed = Vec::new();
+    for name in names {
+        let before = list.len();
+        list.retain(|n| !same_player(n, name));
+        if list.len() != before {
+            removed.push(name.clone());
+        }
+    }
+    removed
+}
+
+/// Factorio usernames are case-insensitive
+pub fn same_player(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+/// Entry of `server-banlist.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BanEntry {
+    pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Keys not known by facts, e.g. address
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+impl<'de> Deserialize<'de> for BanEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'de> {
+        #[derive(Deserialize)]
+        struct Full {
+            username: String,
+            #[serde(default)]
+            reason: Option<String>,
+            #[serde(flatten)]
+            other: Map<String, Value>,
+        }
+
+        /// Factorio accepts plain usernames as well
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Either {
+            Name(String),
+            Full(Full),
+        }
+
+        Ok(match Either::deserialize(deserializer)? {
+            Either::Name(username) => Self {
+                username,
+                reason: None,
+                other: Map::new(),
+            },
+            Either::Full(f) => Self {
+                username: f.username,
+                reason: f.reason,
+                other: f.other,
+            },
+        })
+    }
+}
+
+/// Loads the ban list, missing file is treated as an empty list
+pub fn load_bans(path: &Path) -> Result<Vec<BanEntry>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+pub fn store_bans(path: &Path, bans: &[BanEntry]) {
+    fs::write(path, serde_json::to_string_pretty(bans).unwrap()).expect("Could not write file");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ban_list_formats() {
+        let bans: Vec<BanEntry> = serde_json::from_str(
+            r#"["plain", {"username": "full", "reason": "griefing", "address": "1.2.3.4"}]"#,
+        )
+        .unwrap();
+        assert_eq!(bans[0].username, "plain");
+        assert_eq!(bans[0].reason, None);
+        assert_eq!(bans[1].username, "full");
+        assert_eq!(bans[1].reason.as_deref(), Some("griefing"));
+
+        let output = serde_json::to_value(&bans).unwrap();
+        assert_eq!(output[0], serde_json::json!({"username": "plain"}));
+        assert_eq!(output[1]["address"], "1.2.3.4");
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let mut list = vec!["Alice".to_owned()];
+        let added = add_names(&mut list, &["alice".to_owned(), "Bob".to_owned()]);
+        assert_eq!(added, vec!["Bob"]);
+        let removed = remove_names(&mut list, &["ALICE".to_owned(), "Carol".to_owned()]);
+        assert_eq!(removed, vec!["ALICE"]);
+        assert_eq!(list, vec!["Bob"]);
+    }
+}
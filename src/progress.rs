@@ -0,0 +1,219 @@
+//! Progress of concurrent downloads, drawn in place on a terminal and logged periodically otherwise
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Minimum time between redraws on a terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time between log lines when not on a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+struct FileProgress {
+    name: String,
+    done: u64,
+    total: Option<u64>,
+    finished: bool,
+}
+
+struct State {
+    files: Vec<FileProgress>,
+    started: Instant,
+    last_report: Instant,
+    /// Lines to overwrite on the next redraw
+    drawn_lines: usize,
+}
+
+pub struct Progress {
+    state: Mutex<State>,
+    /// Number of files that will be downloaded
+    file_count: usize,
+    tty: bool,
+}
+impl Progress {
+    pub fn new(file_count: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(State {
+                files: Vec::new(),
+                started: now,
+                last_report: now,
+                drawn_lines: 0,
+            }),
+            file_count,
+            tty: nix::unistd::isatty(2).unwrap_or(false),
+        }
+    }
+
+    /// Registers a download, `total` is its size if known
+    pub fn start(&self, name: &str, total: Option<u64>) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.files.push(FileProgress {
+            name: name.to_owned(),
+            done: 0,
+            total,
+            finished: false,
+        });
+        self.report(&mut state, false);
+        state.files.len() - 1
+    }
+
+    pub fn advance(&self, id: usize, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.files[id].done += bytes;
+        self.report(&mut state, false);
+    }
+
+    /// Restarts a download from the beginning, e.g. when the server can't resume it
+    pub fn reset(&self, id: usize, total: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.files[id].done = 0;
+        state.files[id].total = total;
+        self.report(&mut state, true);
+    }
+
+    pub fn finish_file(&self, id: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.files[id].finished = true;
+        self.report(&mut state, true);
+    }
+
+    /// Prints the final summary
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.files.is_empty() {
+            return;
+        }
+        if self.tty {
+            self.draw(&mut state);
+        } else {
+            log::info!("{}", self.summary(&state));
+        }
+    }
+
+    /// Wraps a reader to count the bytes read as progress of download `id`
+    pub fn reader<R: Read>(&self, id: usize, inner: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            inner,
+            progress: self,
+            id,
+        }
+    }
+
+    fn report(&self, state: &mut State, force: bool) {
+        let elapsed = state.last_report.elapsed();
+        if self.tty && (force || elapsed >= REDRAW_INTERVAL) {
+            self.draw(state);
+            state.last_report = Instant::now();
+        } else if !self.tty && elapsed >= LOG_INTERVAL {
+            log::info!("{}", self.summary(state));
+            state.last_report = Instant::now();
+        }
+    }
+
+    /// Redraws active downloads and the summary line below the previous output
+    fn draw(&self, state: &mut State) {
+        let mut out = String::new();
+        if state.drawn_lines > 0 {
+            write!(out, "\x1b[{}A", state.drawn_lines).unwrap();
+        }
+        out.push_str("\x1b[J");
+
+        let mut lines = 1;
+        for file in state.files.iter().filter(|f| !f.finished) {
+            let total = file.total.map_or_else(|| "?".to_owned(), format_bytes);
+            writeln!(out, "  {} {} / {}", file.name, format_bytes(file.done), total).unwrap();
+            lines += 1;
+        }
+        writeln!(out, "{}", self.summary(state)).unwrap();
+        state.drawn_lines = lines;
+
+        let mut stderr = io::stderr();
+        let _ = stderr.write_all(out.as_bytes());
+        let _ = stderr.flush();
+    }
+
+    /// Files, bytes, rate and remaining time of all downloads
+    fn summary(&self, state: &State) -> String {
+        let finished = state.files.iter().filter(|f| f.finished).count();
+        let done: u64 = state.files.iter().map(|f| f.done).sum();
+        let total: u64 = state.files.iter().map(|f| f.total.unwrap_or(f.done)).sum();
+        let seconds = state.started.elapsed().as_secs_f64();
+        let rate = if seconds > 0.0 {
+            done as f64 / seconds
+        } else {
+            0.0
+        };
+
+        let mut summary = format!(
+            "Downloaded {}/{} files, {} of {}, {}/s",
+            finished,
+            self.file_count,
+            format_bytes(done),
+            format_bytes(total),
+            format_bytes(rate as u64)
+        );
+        // Sizes are only known once downloads start, so the estimate waits for all of them
+        if state.files.len() == self.file_count && finished < self.file_count && rate > 0.0 {
+            let eta = (total.saturating_sub(done) as f64 / rate) as u64;
+            write!(summary, ", ETA {}:{:02}", eta / 60, eta % 60).unwrap();
+        }
+        summary
+    }
+}
+
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+    id: usize,
+}
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.advance(self.id, n as u64);
+        Ok(n)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[test]
+    fn summary() {
+        let progress = Progress::new(2);
+        let a = progress.start("a", Some(100));
+        progress.advance(a, 100);
+        progress.finish_file(a);
+        let b = progress.start("b", None);
+        progress.advance(b, 50);
+
+        let state = progress.state.lock().unwrap();
+        let summary = progress.summary(&state);
+        assert!(summary.starts_with("Downloaded 1/2 files, 150 B of 150 B, "));
+    }
+}
@@ -0,0 +1,308 @@
+//! Reads the header of Factorio save files
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::error::InvalidSave;
+use crate::mod_settings::PropertyTree;
+use crate::modportal::ModInfo;
+use crate::version::Version;
+
+/// Longest string accepted in a header, protects against reading garbage
+const MAX_STRING_LENGTH: u32 = 1 << 16;
+
+/// Start of `level-init.dat` or `level.dat`
+#[derive(Debug)]
+pub struct SaveHeader {
+    /// Factorio version that wrote the save
+    pub version: Version,
+    /// Campaign of the scenario, empty for custom scenarios
+    pub campaign: String,
+    /// Scenario name
+    pub level_name: String,
+    /// Mods that were active when the game was saved
+    pub mods: Vec<ModInfo>,
+    /// Local time the save was written as `YYYY-MM-DD HH:MM`, if read from a zip
+    pub saved_at: Option<String>,
+    /// Current tick of the map, if it could be read
+    pub map_tick: Option<u64>,
+    /// Ticks the game has been played, if it could be read
+    pub ticks_played: Option<u64>,
+}
+impl SaveHeader {
+    /// Reads the header from a save zip
+    pub fn read(path: &Path) -> Result<Self, InvalidSave> {
+        let invalid = |reason: String| InvalidSave(format!("{:?}: {}", path, reason));
+
+        let file = File::open(path).map_err(|e| invalid(e.to_string()))?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| invalid(e.to_string()))?;
+
+        // Uncompressed `level-init.dat` exists since 0.17, older saves only have `level.dat`
+        let mut index = None;
+        for wanted in &["level-init.dat", "level.dat"] {
+            index = (0..archive.len()).find(|&i| {
+                archive
+                    .by_index(i)
+                    .map_or(false, |f| Path::new(f.name()).file_name() == Some(wanted.as_ref()))
+            });
+            if index.is_some() {
+                break;
+            }
+        }
+        let index = index.ok_or_else(|| invalid("no level.dat found".to_owned()))?;
+
+        let entry = archive.by_index(index).map_err(|e| invalid(e.to_string()))?;
+        let time = entry.last_modified();
+        let mut header =
+            Self::parse(entry).map_err(|e| invalid(format!("could not read header: {}", e)))?;
+        header.saved_at = Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            time.year(),
+            time.month(),
+            time.day(),
+            time.hour(),
+            time.minute()
+        ));
+        Ok(header)
+    }
+
+    /// Parses the header, the rest of the level data is not read
+    pub fn parse(r: impl Read) -> io::Result<Self> {
+        let mut r = HeaderReader(r);
+
+        let version = Version::new(r.u16()?.into(), r.u16()?.into(), r.u16()?.into());
+        let _build = r.u16()?;
+        if version < Version::new(0, 16, 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("saves from Factorio {} are not supported", version),
+            ));
+        }
+        if version >= Version::new(0, 17, 0) {
+            let _unknown = r.u8()?;
+        }
+
+        let campaign = r.string()?;
+        let level_name = r.string()?;
+        let _base_mod = r.string()?;
+        let _difficulty = r.u8()?;
+        let _finished = r.u8()?;
+        let _player_won = r.u8()?;
+        let _next_level = r.string()?;
+        let _can_continue = r.u8()?;
+        let _finished_but_continuing = r.u8()?;
+        let _saving_replay = r.u8()?;
+        let _allow_non_admin_debug_options = r.u8()?;
+        let _loaded_from = (r.u8()?, r.u8()?, r.u8()?);
+        let _loaded_from_build = r.u16()?;
+        let _allowed_commands = r.u8()?;
+
+        let count = r.optimized_u32()?;
+        let mut mods = Vec::new();
+        for _ in 0..count {
+            let name = r.string()?;
+            let version = Version::new(
+                r.optimized_u16()?.into(),
+                r.optimized_u16()?.into(),
+                r.optimized_u16()?.into(),
+            );
+            let _crc = r.u32()?;
+            mods.push(ModInfo { name, version });
+        }
+
+        // Only informational, so saves are still accepted if these can't be read
+        let (map_tick, ticks_played) = match Self::parse_ticks(&mut r, version) {
+            Ok((map_tick, ticks_played)) => (Some(map_tick), Some(ticks_played)),
+            Err(error) => {
+                log::debug!("Could not read ticks of save: {}", error);
+                (None, None)
+            },
+        };
+
+        Ok(Self {
+            version,
+            campaign,
+            level_name,
+            mods,
+            saved_at: None,
+            map_tick,
+            ticks_played,
+        })
+    }
+
+    /// Map tick and ticks played, which follow the startup settings since 0.17.
+    /// The tick type was widened in 2.0.
+    fn parse_ticks<R: Read>(r: &mut HeaderReader<R>, version: Version) -> io::Result<(u64, u64)> {
+        if version < Version::new(0, 17, 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not stored before 0.17",
+            ));
+        }
+        let _startup_settings = PropertyTree::read(r)?;
+        if version >= Version::new(2, 0, 0) {
+            Ok((r.u64()?, r.u64()?))
+        } else {
+            Ok((r.u32()?.into(), r.u32()?.into()))
+        }
+    }
+
+    /// Time played as `H:MM:SS`, at 60 ticks per second
+    pub fn playtime(&self) -> Option<String> {
+        let seconds = self.ticks_played? / 60;
+        Some(format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ))
+    }
+
+    /// Active mods, excluding the base game
+    pub fn user_mods(&self) -> impl Iterator<Item = &ModInfo> {
+        self.mods.iter().filter(|m| m.name != "base")
+    }
+}
+
+/// Little-endian primitives used by Factorio serialization
+pub(crate) struct HeaderReader<R>(pub R);
+impl<R: Read> HeaderReader<R> {
+    pub fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.0.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        self.0.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.0.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.0.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    /// One byte, or 0xff followed by the full value
+    pub fn optimized_u16(&mut self) -> io::Result<u16> {
+        match self.u8()? {
+            0xff => self.u16(),
+            b => Ok(b.into()),
+        }
+    }
+
+    /// One byte, or 0xff followed by the full value
+    pub fn optimized_u32(&mut self) -> io::Result<u32> {
+        match self.u8()? {
+            0xff => self.u32(),
+            b => Ok(b.into()),
+        }
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        let len = self.optimized_u32()?;
+        if len > MAX_STRING_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "string too long",
+            ));
+        }
+        let mut buf = vec![0; len as usize];
+        self.0.read_exact(&mut buf)?;
+        String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn string(data: &mut Vec<u8>, s: &str) {
+        data.push(s.len() as u8);
+        data.extend(s.as_bytes());
+    }
+
+    #[test]
+    fn parse_header() {
+        let mut data = Vec::new();
+        for n in &[0u16, 18, 27, 52] {
+            data.extend(&n.to_le_bytes());
+        }
+        data.push(0);
+        string(&mut data, "transport-belt-madness");
+        string(&mut data, "level");
+        string(&mut data, "base");
+        data.extend(&[0, 0, 0]);
+        string(&mut data, "");
+        data.extend(&[1, 0, 0, 0]);
+        data.extend(&[0, 18, 27, 52, 0, 1]);
+        data.push(2);
+        string(&mut data, "base");
+        data.extend(&[0, 18, 27, 0, 0, 0, 0]);
+        string(&mut data, "rso-mod");
+        data.extend(&[6, 0xff, 0x2c, 0x01, 11, 1, 2, 3, 4]);
+        // Empty dictionary of startup settings
+        data.extend(&[5, 0, 0, 0, 0, 0]);
+        data.extend(&1_000_000u32.to_le_bytes());
+        data.extend(&216_000u32.to_le_bytes());
+
+        let header = SaveHeader::parse(data.as_slice()).unwrap();
+        assert_eq!(header.version, Version::new(0, 18, 27));
+        assert_eq!(header.campaign, "transport-belt-madness");
+        assert_eq!(header.level_name, "level");
+        assert_eq!(header.mods.len(), 2);
+        let user_mods: Vec<_> = header.user_mods().collect();
+        assert_eq!(user_mods.len(), 1);
+        assert_eq!(user_mods[0].name, "rso-mod");
+        assert_eq!(user_mods[0].version, Version::new(6, 300, 11));
+        assert_eq!(header.map_tick, Some(1_000_000));
+        assert_eq!(header.playtime().as_deref(), Some("1:00:00"));
+    }
+
+    #[test]
+    fn truncated_ticks() {
+        let mut data = Vec::new();
+        for n in &[1u16, 1, 0, 0] {
+            data.extend(&n.to_le_bytes());
+        }
+        data.push(0);
+        string(&mut data, "");
+        string(&mut data, "level");
+        string(&mut data, "base");
+        data.extend(&[0, 0, 0]);
+        string(&mut data, "");
+        data.extend(&[1, 0, 0, 0]);
+        data.extend(&[1, 1, 0, 0, 0, 0]);
+        data.push(1);
+        string(&mut data, "base");
+        data.extend(&[1, 1, 0, 0, 0, 0, 0]);
+        // Startup settings without the ticks after them
+        data.extend(&[5, 0, 0, 0, 0, 0]);
+        data.extend(&[1, 2]);
+
+        let header = SaveHeader::parse(data.as_slice()).unwrap();
+        assert_eq!(header.version, Version::new(1, 1, 0));
+        assert_eq!(header.mods.len(), 1);
+        assert_eq!(header.map_tick, None);
+        assert_eq!(header.playtime(), None);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let data = [0, 0, 18, 0, 27, 0];
+        assert!(SaveHeader::parse(&data[..]).is_err());
+    }
+}
@@ -1,83 +1,290 @@
 //! Builds file configuration for a server
 
-use crossbeam_channel::{bounded, unbounded};
+use crossbeam_channel::{bounded, unbounded, Sender};
+use nix::sys::stat::Mode;
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::os::unix::fs::symlink;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::os::unix::fs::{symlink, OpenOptionsExt};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::{self, JoinHandle};
+use tar::{Archive, Builder, EntryType, Header};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
 
+use crate::benchmark::BenchmarkResult;
 use crate::config::*;
 use crate::download;
-use crate::error::DowngradingNotAllowed;
-use crate::modportal::{load_mod_list_json, ModDownloader, ModInfo};
+use crate::error::{
+    BenchmarkFailed, DowngradingNotAllowed, IncompatibleMods, InvalidBundle, InvalidMod,
+    InvalidModSettings, InvalidScenario, InvalidServerSettings, MapPreviewFailed, NoFreePort,
+    NoSuchSnapshot, PortAlreadyAllocated, SaveTooNew, WorldAlreadyExists, WorldIsRunning,
+};
+use crate::mod_settings::ModSettings;
+use crate::modportal::{
+    latest_compatible, load_mod_list_json, ModDownloader, ModInfo, ModInfoJson, ModPortal,
+};
+use crate::player_lists::{self, BanEntry, PlayerList};
+use crate::save::SaveHeader;
 use crate::server_process::{self, message};
-use crate::version::{ResolvedVersionReq, Version};
+use crate::server_settings::ServerSettings;
+use crate::version::{ResolvedVersionReq, Version, VersionReq};
 
 /// Server info data version format
 const SERVER_INFO_VERSION: u64 = 1;
 
+/// UDP port Factorio uses if none is specified
+pub const DEFAULT_PORT: u16 = 34197;
+
+/// Settings files stored in the world directory
+const SETTINGS_FILES: &[&str] = &[
+    "map-gen-settings.json",
+    "map-settings.json",
+    "server-settings.json",
+    "server-adminlist.json",
+    "server-whitelist.json",
+    "server-banlist.json",
+];
+
+/// Bundle info data version format
+const BUNDLE_INFO_VERSION: u64 = 1;
+
+/// Bundle contents that are not world files
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleInfo {
+    _version: u64,
+    /// Exact versions of all mods of the world
+    mods: BTreeMap<String, Version>,
+    /// Whether mod zips are stored under `mods/`
+    includes_mods: bool,
+}
+
 /// Server data to persist to disk
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerInfo {
     _version: u64,
     pub config: MetaConfig,
     pub current_version: Version,
+    /// Mods kept at a specific version, these are not updated
+    #[serde(default)]
+    pub pinned_mods: BTreeMap<String, Version>,
+    /// Scenario to start from while the world has no save yet
+    #[serde(default)]
+    pub scenario: Option<String>,
+    /// Mods installed from outside the mod portal, these are not updated from the portal
+    #[serde(default)]
+    pub local_mods: BTreeMap<String, LocalMod>,
+}
+
+/// Source of a mod that is not on the mod portal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalMod {
+    /// Zip copied into the mod store, copied again when updating mods
+    File(PathBuf),
+    /// Unpacked directory linked into the world, changes apply on the next start
+    Dir(PathBuf),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Server {
     pub name: String,
     pub dir: PathBuf,
     pub info: ServerInfo,
 }
 impl Server {
-    /// Creates a new server from name and config
-    pub fn create(name: String, config: CreateConfig) -> Result<Self, Box<dyn std::error::Error>> {
-        let dir = crate::dirs::new_world(&name)?;
+    /// Creates a new server from name and config,
+    /// installing mods before the world is generated.
+    /// With a scenario, the world is created when the server is first started.
+    /// `seed` overrides the one in map-gen-settings.
+    pub fn create_with_mods(
+        name: String, config: CreateConfig, mods: Vec<String>,
+        pinned_mods: BTreeMap<String, Version>, scenario: Option<&str>, seed: Option<u32>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::create_with(name, config, seed, |server| {
+            server.add_mods(mods)?;
+            for (mod_name, version) in pinned_mods {
+                server.pin_mod(&mod_name, version)?;
+            }
+            if let Some(scenario) = scenario {
+                server.set_scenario(scenario)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Renders a map preview PNG for creation settings without creating a world.
+    /// Mods are installed as well, since they can change map generation.
+    pub fn preview_map(
+        config: &CreateConfig, mut mods: Vec<String>, pinned_mods: &BTreeMap<String, Version>,
+        seed: Option<u32>, output: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let output = std::env::current_dir()?.join(output);
         let current_version = download::require(config.meta.factorio.clone())?;
+        let s = Self::scratch("preview", config.meta.clone(), current_version);
+
+        if let Some(path) = &config.import.mod_list {
+            mods.extend(load_mod_list_json(path)?);
+        }
+        mods.retain(|m| !pinned_mods.contains_key(m));
+        s.add_mods(mods)?;
+        if !pinned_mods.is_empty() {
+            let downloader = ModDownloader::new()?;
+            for (mod_name, &version) in pinned_mods {
+                s.link_mod(&downloader.require_exact(mod_name, version)?);
+            }
+        }
+        // Startup settings of mods can change map generation
+        if let Some(path) = &config.import.mod_settings {
+            s.import_mod_settings(path)?;
+        }
+
+        let mut cmd = s.command_base();
+        cmd.args(&["--config", "config.ini", "--mod-directory", "factorio/mods/"]);
+        cmd.arg("--generate-map-preview").arg(&output);
+        if let Some(path) = &config.map_gen_settings {
+            s.copy_file(path, "map-gen-settings.json");
+            cmd.args(&["--map-gen-settings", "map-gen-settings.json"]);
+        }
+        if let Some(seed) = seed {
+            cmd.arg("--map-gen-seed").arg(seed.to_string());
+        }
+
+        log::info!("Rendering map preview");
+        let result = cmd.output()?;
+        if !result.status.success() {
+            return Err(Box::new(MapPreviewFailed(
+                String::from_utf8_lossy(&result.stdout).into_owned(),
+            )));
+        }
+
+        log::info!("Map preview written to {:?}", output);
+        Ok(())
+    }
 
+    /// Temporary server for running Factorio outside of a world
+    fn scratch(purpose: &str, config: MetaConfig, current_version: Version) -> Self {
         let s = Self {
-            dir,
-            name,
+            name: purpose.to_owned(),
+            dir: crate::dirs::scratch_dir(purpose),
             info: ServerInfo {
                 _version: SERVER_INFO_VERSION,
-                config: config.meta.clone(),
+                config,
                 current_version,
+                pinned_mods: BTreeMap::new(),
+                scenario: None,
+                local_mods: BTreeMap::new(),
             },
         };
-
         s.create_config_ini();
-        s.create_handle_files(&config)?;
-        s.save();
-        s.generate();
+        s
+    }
 
-        Ok(s)
+    /// Creates a new server from name and config,
+    /// calling `setup` before the world is generated
+    fn create_with<F>(
+        name: String, config: CreateConfig, seed: Option<u32>, setup: F,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where F: FnOnce(&mut Self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut meta = config.meta.clone();
+        Self::assign_port(&mut meta, None)?;
+        let current_version = download::require(meta.factorio.clone())?;
+
+        in_new_world(&name, |dir| {
+            let mut s = Self {
+                dir,
+                name: name.clone(),
+                info: ServerInfo {
+                    _version: SERVER_INFO_VERSION,
+                    config: meta,
+                    current_version,
+                    pinned_mods: BTreeMap::new(),
+                    scenario: None,
+                    local_mods: BTreeMap::new(),
+                },
+            };
+
+            s.create_config_ini();
+            s.create_handle_files(&config)?;
+            s.save();
+            setup(&mut s)?;
+            if s.info.scenario.is_none() {
+                s.generate(seed);
+            }
+
+            Ok(s)
+        })
     }
 
     /// Creates a new, empty server from name and some configuration
-    pub fn create_empty(
-        name: String, config: ImportConfig, meta: MetaConfig,
+    fn create_empty(
+        name: String, config: ImportConfig, mut meta: MetaConfig, current_version: Version,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let dir = crate::dirs::new_world(&name)?;
-        let current_version = download::require(meta.factorio.clone())?;
-
-        let s = Self {
-            dir,
-            name,
-            info: ServerInfo {
-                _version: SERVER_INFO_VERSION,
-                config: meta,
-                current_version,
-            },
-        };
+        Self::assign_port(&mut meta, None)?;
+
+        in_new_world(&name, |dir| {
+            let s = Self {
+                dir,
+                name: name.clone(),
+                info: ServerInfo {
+                    _version: SERVER_INFO_VERSION,
+                    config: meta,
+                    current_version,
+                    pinned_mods: BTreeMap::new(),
+                    scenario: None,
+                    local_mods: BTreeMap::new(),
+                },
+            };
+
+            s.create_config_ini();
+            s.import_handle_files(&config)?;
+            s.save();
+
+            Ok(s)
+        })
+    }
 
-        s.create_config_ini();
-        s.import_handle_files(&config)?;
-        s.save();
+    /// Creates a new server from an existing save,
+    /// refusing saves written by a newer Factorio version.
+    /// `confirm_mods` is asked before creating the world whether to install
+    /// mods of the save that are not in the imported mod list.
+    pub fn import<F>(
+        name: String, path: &Path, config: ImportConfig, meta: MetaConfig, confirm_mods: F,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&[&ModInfo]) -> Result<bool, Box<dyn std::error::Error>>,
+    {
+        let save = SaveHeader::read(path)?;
+        log::info!("Save is from Factorio {}", save.version);
+
+        let version = download::require(meta.factorio.clone())?;
+        if save.version > version {
+            return Err(Box::new(SaveTooNew {
+                save: save.version,
+                factorio: version,
+            }));
+        }
 
+        let listed: HashSet<String> = match &config.mod_list {
+            Some(mod_list_file) => load_mod_list_json(mod_list_file)?.into_iter().collect(),
+            None => HashSet::new(),
+        };
+        let missing: Vec<_> = save
+            .user_mods()
+            .filter(|m| !listed.contains(&m.name))
+            .collect();
+        let add_missing = !missing.is_empty() && confirm_mods(&missing)?;
+
+        let s = Self::create_empty(name, config, meta, version)?;
+        fs::copy(path, s.dir.join("world.zip"))?;
+        if add_missing {
+            s.add_mods(missing.into_iter().map(|m| m.name.clone()).collect())?;
+        }
         Ok(s)
     }
 
@@ -97,12 +304,307 @@ impl Server {
         Ok(Self { dir, name, info })
     }
 
+    /// Copies this server into a new world, optionally starting from an autosave
+    pub fn clone_as(
+        &self, name: String, snapshot: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let save = match snapshot {
+            Some(snapshot) => self.snapshot(snapshot)?,
+            None => {
+                if self.is_running() {
+                    return Err(Box::new(WorldIsRunning(self.name.clone())));
+                }
+                self.dir.join("world.zip")
+            },
+        };
+
+        let mut info = self.info.clone();
+        info.config.port = None;
+        Self::assign_port(&mut info.config, None)?;
+        // Clones are often for testing, and shouldn't run next to the original by default
+        info.config.autostart = false;
+
+        in_new_world(&name, |dir| {
+            let s = Self { dir, name: name.clone(), info };
+
+            // Scenario worlds have no save before their first start
+            if save.exists() || s.info.scenario.is_none() {
+                fs::copy(&save, s.dir.join("world.zip"))?;
+            }
+            if self.dir.join("scenarios").exists() {
+                copy_dir(&self.dir.join("scenarios"), &s.dir.join("scenarios"))?;
+            }
+            for file in SETTINGS_FILES {
+                let path = self.dir.join(file);
+                if path.exists() {
+                    fs::copy(&path, s.dir.join(file))?;
+                }
+            }
+
+            // Mod settings and lists are stored next to the mod links
+            for entry in fs::read_dir(self.dir.join("factorio/mods"))? {
+                let path = entry?.path();
+                if fs::symlink_metadata(&path)?.is_file() {
+                    fs::copy(&path, s.dir.join("factorio/mods").join(path.file_name().unwrap()))?;
+                }
+            }
+            for mod_info in self.mods() {
+                s.link_mod(&mod_info);
+            }
+            for (mod_name, source) in &s.info.local_mods {
+                if let LocalMod::Dir(path) = source {
+                    s.link_mod_dir(mod_name, path)?;
+                }
+            }
+
+            s.create_config_ini();
+            s.save();
+
+            Ok(s)
+        })
+    }
+
+    /// Creates a new server from a bundle written by `export_bundle`.
+    /// Mods are taken from the bundle if included, and downloaded otherwise.
+    /// `meta` overrides the exported server config.
+    pub fn import_bundle(
+        name: String, path: &Path, config: &ImportConfig, meta: &MetaConfigUpdate,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if crate::dirs::get_world(&name).is_ok() {
+            return Err(Box::new(WorldAlreadyExists(name)));
+        }
+
+        // The world is prepared outside of `worlds/` and moved there once complete,
+        // so that a failed import leaves nothing behind
+        let staging = crate::dirs::scratch_dir(&format!("import-{}", app_dirs::sanitized(&name)));
+        let result = Self::import_bundle_into(staging.clone(), name.clone(), path, config, meta)
+            .and_then(|s| s.rename(name));
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        result
+    }
+
+    fn import_bundle_into(
+        dir: PathBuf, name: String, path: &Path, config: &ImportConfig, meta: &MetaConfigUpdate,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        crate::dirs::create_mods_dir();
+
+        let mut bundle: Option<BundleInfo> = None;
+        let mut info: Option<ServerInfo> = None;
+        let mut archive = Archive::new(XzDecoder::new(File::open(path)?));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+            // Links could make Factorio write outside of the world
+            if entry.header().entry_type() != EntryType::Regular {
+                return Err(Box::new(InvalidBundle(format!(
+                    "{:?} is not a regular file",
+                    entry_path
+                ))));
+            }
+            let parent = entry_path.parent().and_then(Path::to_str).unwrap_or_default();
+            let file = entry_path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+
+            match (parent, file) {
+                ("", "bundle.json") => bundle = Some(serde_json::from_reader(&mut entry)?),
+                ("", "facts.json") => info = Some(serde_json::from_reader(&mut entry)?),
+                ("", f) if f == "world.zip" || SETTINGS_FILES.contains(&f) => {
+                    entry.unpack(dir.join(f))?;
+                },
+                ("factorio/mods", f) if !f.is_empty() => {
+                    entry.unpack(dir.join("factorio/mods").join(f))?;
+                },
+                // Bundled mods are untrusted, so they stay out of the shared mod store
+                ("mods", f) => {
+                    let mod_info = ModInfo::try_from_file_name(f)?;
+                    entry.unpack(dir.join("factorio/mods").join(mod_info.file_name()))?;
+                },
+                _ if entry_path.starts_with("scenarios") => {
+                    // Refuses paths leaving the world directory
+                    if !entry.unpack_in(&dir)? {
+                        return Err(Box::new(InvalidBundle(format!(
+                            "{:?} is outside of the world",
+                            entry_path
+                        ))));
+                    }
+                },
+                _ => log::warn!("Ignoring unknown bundle entry {:?}", entry_path),
+            }
+        }
+
+        let (bundle, mut info) = match (bundle, info) {
+            (Some(bundle), Some(info)) => (bundle, info),
+            _ => {
+                return Err(Box::new(InvalidBundle(
+                    "bundle.json or facts.json missing".to_owned(),
+                )));
+            },
+        };
+        if bundle._version != BUNDLE_INFO_VERSION || info._version != SERVER_INFO_VERSION {
+            return Err(Box::new(InvalidBundle(
+                "created by an unsupported version of facts".to_owned(),
+            )));
+        }
+
+        info.config.apply_update(meta.clone());
+        // Keep the exported port if it's free here, unless another one was asked for
+        if meta.port.is_some() {
+            Self::assign_port(&mut info.config, None)?;
+        } else if Self::assign_port(&mut info.config, None).is_err() {
+            info.config.port = None;
+            Self::assign_port(&mut info.config, None)?;
+        }
+        download::require(VersionReq::Specific(info.current_version.to_string()))?;
+
+        let s = Self { dir, name, info };
+        s.create_config_ini();
+
+        let mut downloader = None;
+        for (mod_name, &version) in &bundle.mods {
+            let mut mod_info = ModInfo {
+                name: mod_name.clone(),
+                version,
+            };
+            if s.installed_mod_path(&mod_info).exists() {
+                continue;
+            }
+            if !mod_info.path().exists() {
+                if downloader.is_none() {
+                    downloader = Some(ModDownloader::new()?);
+                }
+                mod_info = downloader.as_ref().unwrap().require_exact(mod_name, version)?;
+            }
+            s.link_mod(&mod_info);
+        }
+
+        s.import_handle_files(config)?;
+        s.save();
+
+        Ok(s)
+    }
+
+    /// Writes the save, settings and exact mod versions to a `.tar.xz` bundle,
+    /// optionally including the mod zips
+    pub fn export_bundle(
+        &self, path: &Path, include_mods: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Local mod zips are stored in the world directory and exported with it
+        let mods: Vec<ModInfo> = self
+            .mods()
+            .into_iter()
+            .filter(|m| !self.info.local_mods.contains_key(&m.name))
+            .collect();
+        let bundle = BundleInfo {
+            _version: BUNDLE_INFO_VERSION,
+            mods: mods.iter().map(|m| (m.name.clone(), m.version)).collect(),
+            includes_mods: include_mods,
+        };
+
+        let mut archive = Builder::new(XzEncoder::new(File::create(path)?, 6));
+
+        let data = serde_json::to_vec_pretty(&bundle).unwrap();
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append_data(&mut header, "bundle.json", data.as_slice())?;
+
+        for file in ["world.zip", "facts.json"].iter().chain(SETTINGS_FILES) {
+            let file_path = self.dir.join(file);
+            if file_path.exists() {
+                archive.append_path_with_name(&file_path, file)?;
+            }
+        }
+
+        // Mod settings, lists and zips stored in the world,
+        // mod links are restored from `bundle.json`
+        for entry in fs::read_dir(self.dir.join("factorio/mods"))? {
+            let file_path = entry?.path();
+            if fs::symlink_metadata(&file_path)?.is_file() {
+                let name = Path::new("factorio/mods").join(file_path.file_name().unwrap());
+                archive.append_path_with_name(&file_path, name)?;
+            }
+        }
+
+        if include_mods {
+            for mod_info in &mods {
+                if fs::symlink_metadata(self.installed_mod_path(mod_info))?.is_file() {
+                    continue;
+                }
+                let name = Path::new("mods").join(mod_info.file_name());
+                archive.append_path_with_name(mod_info.path(), name)?;
+            }
+        }
+
+        // Scenario worlds have no save before their first start
+        if self.dir.join("scenarios").exists() {
+            append_files(&mut archive, &self.dir.join("scenarios"), Path::new("scenarios"))?;
+        }
+
+        archive.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    /// Moves this server to a new name, which also changes its directory
+    pub fn rename(self, name: String) -> Result<Self, Box<dyn std::error::Error>> {
+        if self.is_running() {
+            return Err(Box::new(WorldIsRunning(self.name)));
+        }
+
+        let dir = crate::dirs::rename_world(&self.dir, &name)?;
+        let s = Self {
+            dir,
+            name,
+            info: self.info,
+        };
+        // Write data path is absolute
+        s.create_config_ini();
+
+        Ok(s)
+    }
+
+    /// Autosaves written by Factorio into `saves/`
+    pub fn snapshots(&self) -> Vec<String> {
+        let mut snapshots: Vec<String> = fs::read_dir(self.dir.join("saves"))
+            .map(|paths| {
+                paths
+                    .filter_map(|p| {
+                        let path = p.ok()?.path();
+                        let name = path.file_name()?.to_str()?;
+                        Some(name.strip_suffix(".zip")?.to_owned())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        snapshots.sort();
+        snapshots
+    }
+
+    /// Path of an autosave by name, with or without `.zip`
+    pub fn snapshot(&self, name: &str) -> Result<PathBuf, NoSuchSnapshot> {
+        let name = name.strip_suffix(".zip").unwrap_or(name);
+        let path = self.dir.join("saves").join(format!("{}.zip", name));
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(NoSuchSnapshot {
+                name: name.to_owned(),
+                available: self.snapshots(),
+            })
+        }
+    }
+
     /// Updates server config from an ImportConfig
     pub fn update_config(
         &mut self, config: ImportConfig, meta: MetaConfigUpdate,
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.import_handle_files(&config)?;
+        let port_changed = meta.port.is_some();
         self.info.config.apply_update(meta);
+        if port_changed {
+            Self::assign_port(&mut self.info.config, Some(&self.dir))?;
+        }
 
         if let Some(resolved) = self.latest_version() {
             if resolved.version < self.info.current_version {
@@ -118,6 +620,56 @@ impl Server {
         Ok(())
     }
 
+    /// UDP port this server listens on
+    pub fn port(&self) -> u16 {
+        self.info.config.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    /// All worlds except the one in directory `exclude`
+    fn other_worlds(exclude: Option<&Path>) -> impl Iterator<Item = Self> + '_ {
+        crate::dirs::list_worlds()
+            .into_iter()
+            .filter_map(|world| Self::get(world).ok())
+            .filter(move |server| Some(server.dir.as_path()) != exclude)
+    }
+
+    /// Worlds using the given UDP port, excluding the world in directory `exclude`
+    fn worlds_using_port(port: u16, exclude: Option<&Path>) -> Vec<Self> {
+        Self::other_worlds(exclude)
+            .filter(|server| server.port() == port)
+            .collect()
+    }
+
+    /// Chooses a free port if none is set, and otherwise
+    /// makes sure that no other world is configured to use the port
+    fn assign_port(
+        meta: &mut MetaConfig, own_dir: Option<&Path>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(port) = meta.port {
+            if let Some(other) = Self::worlds_using_port(port, own_dir).into_iter().next() {
+                return Err(Box::new(PortAlreadyAllocated {
+                    port,
+                    world: other.name,
+                }));
+            }
+            return Ok(());
+        }
+
+        let used: HashSet<u16> = Self::other_worlds(own_dir)
+            .map(|server| server.port())
+            .collect();
+
+        let port = (DEFAULT_PORT..=u16::MAX)
+            .find(|port| {
+                !used.contains(port) && UdpSocket::bind((Ipv4Addr::UNSPECIFIED, *port)).is_ok()
+            })
+            .ok_or(NoFreePort)?;
+
+        log::info!("Using UDP port {}", port);
+        meta.port = Some(port);
+        Ok(())
+    }
+
     /// Saves server configuration
     pub fn save(&self) {
         fs::write(
@@ -162,29 +714,240 @@ impl Server {
         &self, config: &ImportConfig,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(path) = &config.server_settings {
-            self.copy_file(path, "server-settings.json");
+            self.set_server_settings(&ServerSettings::load(path)?);
         }
 
-        let mut admins: Vec<String> = config.add_admin.clone();
-        if let Some(path) = &config.server_adminlist {
-            let content = fs::read_to_string(path).expect("Could not read file");
-            let file_admins: Vec<String> = serde_json::from_str(&content).expect("Invalid JSON");
-            admins.extend(file_admins);
-        }
-        fs::write(
-            self.dir.join("server-adminlist.json"),
-            serde_json::to_string(&admins).unwrap(),
-        )
-        .expect("Could not write file");
+        let adminlist = self.dir.join(PlayerList::Admins.file_name());
+        let mut admins = if let Some(path) = &config.server_adminlist {
+            player_lists::load_names(path)?
+        } else {
+            player_lists::load_names(&adminlist)?
+        };
+        player_lists::add_names(&mut admins, &config.add_admin);
+        player_lists::store_names(&adminlist, &admins);
 
         if let Some(mod_list_file) = &config.mod_list {
             let mods = load_mod_list_json(mod_list_file)?;
             self.add_mods(mods)?;
         }
 
+        if let Some(path) = &config.mod_settings {
+            self.import_mod_settings(path)?;
+        }
+
         Ok(())
     }
 
+    /// Installs a scenario from a local directory, or the mod of a `mod-name/scenario-name`.
+    /// Other names refer to scenarios that come with Factorio.
+    pub fn set_scenario(&mut self, scenario: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Path::new(scenario);
+        let name = if path.is_dir() {
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| InvalidScenario(scenario.to_owned()))?;
+            if !path.join("control.lua").exists() {
+                log::warn!("Scenario {:?} has no control.lua", path);
+            }
+            copy_dir(path, &self.dir.join("scenarios").join(name))?;
+            name.to_owned()
+        } else if scenario.contains('/') {
+            let mod_name = scenario.split('/').next().unwrap();
+            if !self.mods().iter().any(|m| m.name == mod_name) {
+                self.add_mods(vec![mod_name.to_owned()])?;
+            }
+            scenario.to_owned()
+        } else {
+            scenario.to_owned()
+        };
+
+        log::info!("World will be created from scenario {}", name);
+        self.info.scenario = Some(name);
+        self.save();
+        Ok(())
+    }
+
+    /// Until a scenario world has been saved as `world.zip`,
+    /// Factorio writes it into `saves/`, so the newest save there is used
+    fn adopt_scenario_save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let world = self.dir.join("world.zip");
+        if self.info.scenario.is_none() || world.exists() {
+            return Ok(());
+        }
+
+        let newest = fs::read_dir(self.dir.join("saves"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let modified = path.metadata().ok()?.modified().ok()?;
+                Some((modified, path)).filter(|(_, p)| p.extension() == Some("zip".as_ref()))
+            })
+            .max();
+
+        if let Some((_, path)) = newest {
+            log::info!("Continuing scenario world from {:?}", path);
+            fs::copy(path, world)?;
+        }
+        Ok(())
+    }
+
+    /// Loads `server-settings.json`, or defaults if the server doesn't have one
+    pub fn server_settings(&self) -> Result<ServerSettings, InvalidServerSettings> {
+        ServerSettings::load_or_default(&self.dir.join("server-settings.json"))
+    }
+
+    /// Writes `server-settings.json`
+    pub fn set_server_settings(&self, settings: &ServerSettings) {
+        settings.store(&self.dir.join("server-settings.json"));
+    }
+
+    /// Loads `mod-settings.dat`, or empty settings if Factorio hasn't written it yet
+    pub fn mod_settings(&self) -> Result<ModSettings, InvalidModSettings> {
+        ModSettings::load_or_new(&self.mod_settings_path(), self.info.current_version)
+    }
+
+    /// Writes `mod-settings.dat`, startup settings apply when the server is restarted
+    pub fn set_mod_settings(&self, settings: &ModSettings) {
+        settings.store(&self.mod_settings_path());
+    }
+
+    /// Replaces mod settings with a `mod-settings.dat` or a JSON export
+    pub fn import_mod_settings(&self, path: &Path) -> Result<(), InvalidModSettings> {
+        let settings = ModSettings::import(path, &self.mod_settings()?)?;
+        self.set_mod_settings(&settings);
+        Ok(())
+    }
+
+    fn mod_settings_path(&self) -> PathBuf {
+        self.dir.join("factorio/mods/mod-settings.dat")
+    }
+
+    /// Players on an admin list or a whitelist
+    pub fn player_list(&self, list: PlayerList) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        player_lists::load_names(&self.dir.join(list.file_name()))
+    }
+
+    /// Adds players to a list, applying the change to the running server as well
+    pub fn add_to_player_list(
+        &self, list: PlayerList, players: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut names = self.player_list(list)?;
+        let added = player_lists::add_names(&mut names, players);
+        player_lists::store_names(&self.dir.join(list.file_name()), &names);
+        for player in added {
+            self.console_command(&list.add_command(&player));
+        }
+        Ok(())
+    }
+
+    /// Removes players from a list, applying the change to the running server as well
+    pub fn remove_from_player_list(
+        &self, list: PlayerList, players: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut names = self.player_list(list)?;
+        let removed = player_lists::remove_names(&mut names, players);
+        player_lists::store_names(&self.dir.join(list.file_name()), &names);
+        for player in players {
+            if removed.contains(player) {
+                self.console_command(&list.remove_command(player));
+            } else {
+                log::warn!("Player {:?} is not on the list", player);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bans(&self) -> Result<Vec<BanEntry>, Box<dyn std::error::Error>> {
+        player_lists::load_bans(&self.dir.join("server-banlist.json"))
+    }
+
+    /// Bans a player, applying the change to the running server as well
+    pub fn ban(
+        &self, player: &str, reason: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut bans = self.bans()?;
+        if bans.iter().any(|b| player_lists::same_player(&b.username, player)) {
+            log::warn!("Player {:?} is already banned", player);
+            return Ok(());
+        }
+
+        let command = match &reason {
+            Some(reason) => format!("/ban {} {}", player, reason),
+            None => format!("/ban {}", player),
+        };
+        bans.push(BanEntry {
+            username: player.to_owned(),
+            reason,
+            other: Default::default(),
+        });
+        player_lists::store_bans(&self.dir.join("server-banlist.json"), &bans);
+        self.console_command(&command);
+        Ok(())
+    }
+
+    /// Unbans players, applying the change to the running server as well
+    pub fn unban(&self, players: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut bans = self.bans()?;
+        for player in players {
+            let before = bans.len();
+            bans.retain(|b| !player_lists::same_player(&b.username, player));
+            if bans.len() == before {
+                log::warn!("Player {:?} is not banned", player);
+            } else {
+                self.console_command(&format!("/unban {}", player));
+            }
+        }
+        player_lists::store_bans(&self.dir.join("server-banlist.json"), &bans);
+        Ok(())
+    }
+
+    fn pid_file(&self) -> PathBuf {
+        self.dir.join("facts.pid")
+    }
+
+    /// Named pipe forwarding console commands to the running server
+    fn console_file(&self) -> PathBuf {
+        self.dir.join("console.fifo")
+    }
+
+    /// Checks whether a facts process is currently running this server
+    pub fn is_running(&self) -> bool {
+        let pid = match fs::read_to_string(self.pid_file()) {
+            Ok(content) => content,
+            Err(_) => return false,
+        };
+        match pid.trim().parse::<i32>() {
+            Ok(pid) => nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    /// Sends a console command to the running server.
+    /// Returns false if the server is not running.
+    pub fn console_command(&self, command: &str) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+
+        let pipe = OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
+            .open(self.console_file());
+
+        match pipe.and_then(|mut pipe| writeln!(pipe, "{}", command)) {
+            Ok(()) => {
+                log::info!("Applied to running server: {}", command);
+                true
+            },
+            Err(error) => {
+                log::warn!("Could not send command to running server: {}", error);
+                false
+            },
+        }
+    }
+
     /// List all mods installed on this server
     pub fn mods(&self) -> Vec<ModInfo> {
         let mut pb = self.dir.clone();
@@ -232,34 +995,224 @@ impl Server {
         }
     }
 
-    pub fn add_mods(&self, mods: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    /// Installs the latest compatible releases of mods.
+    /// Pinned and local mods are skipped, they have to be removed first.
+    pub fn add_mods(&self, mut mods: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+        mods.retain(|name| {
+            if let Some(version) = self.info.pinned_mods.get(name) {
+                log::warn!(
+                    "Mod {} is pinned to {}, remove it first to install the latest release",
+                    name,
+                    version
+                );
+                false
+            } else if self.info.local_mods.contains_key(name) {
+                log::warn!(
+                    "Mod {} is installed locally, remove it first to install it from the portal",
+                    name
+                );
+                false
+            } else {
+                true
+            }
+        });
+        if mods.is_empty() {
+            return Ok(());
+        }
+
         let downloader = ModDownloader::new()?;
         log::info!("Downloading mods");
-        for modname in mods {
-            let mod_info = downloader.require(&modname, self.info.current_version)?;
+        for mod_info in downloader.require_all(&mods, self.info.current_version)? {
             self.link_mod(&mod_info);
         }
         log::info!("Download complete");
         Ok(())
     }
 
-    pub fn remove_mods(&self, mods: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    /// Installs a mod zip that is not on the mod portal
+    pub fn add_mod_file(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let path = fs::canonicalize(path)?;
+        let mod_info = self.copy_mod_file(&path)?;
+        log::info!("Installed {} {} from {:?}", mod_info.name, mod_info.version, path);
+        self.track_local_mod(mod_info.name, LocalMod::File(path));
+        Ok(())
+    }
+
+    /// Links an unpacked mod directory into this world
+    pub fn add_mod_dir(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let path = fs::canonicalize(path)?;
+        let mod_info = ModInfoJson::read(&path)?.validate(&path, self.info.current_version)?;
+        for installed_mod in self.mods() {
+            if installed_mod.name == mod_info.name {
+                self.unlink_mod(&installed_mod);
+            }
+        }
+        self.link_mod_dir(&mod_info.name, &path)?;
+        log::info!("Linked {} {} from {:?}", mod_info.name, mod_info.version, path);
+        self.track_local_mod(mod_info.name, LocalMod::Dir(path));
+        Ok(())
+    }
+
+    /// Validates a mod zip and copies it into this world. Local zips are kept out of the
+    /// shared mod store, where they could replace a portal release of the same version.
+    fn copy_mod_file(&self, path: &Path) -> Result<ModInfo, Box<dyn std::error::Error>> {
+        let mod_info = ModInfoJson::read(path)?.validate(path, self.info.current_version)?;
+        for installed_mod in self.mods() {
+            if installed_mod.name == mod_info.name {
+                self.unlink_mod(&installed_mod);
+            }
+        }
+        self.unlink_mod_dir(&mod_info.name)?;
+        fs::copy(path, self.installed_mod_path(&mod_info))?;
+        Ok(mod_info)
+    }
+
+    /// Path of a mod zip in `factorio/mods/` of this world
+    fn installed_mod_path(&self, mod_info: &ModInfo) -> PathBuf {
+        self.dir.join("factorio/mods").join(mod_info.file_name())
+    }
+
+    /// Links a mod directory as `factorio/mods/<name>`, replacing an existing link
+    fn link_mod_dir(&self, name: &str, path: &Path) -> std::io::Result<()> {
+        if !path.exists() {
+            log::warn!("Directory of mod {} is missing: {:?}", name, path);
+        }
+        self.unlink_mod_dir(name)?;
+        symlink(path, self.dir.join("factorio/mods").join(name))
+    }
+
+    /// Removes the link of a mod directory, if there is one
+    fn unlink_mod_dir(&self, name: &str) -> std::io::Result<()> {
+        let dest = self.dir.join("factorio/mods").join(name);
+        if fs::symlink_metadata(&dest).map_or(false, |m| m.file_type().is_symlink()) {
+            fs::remove_file(dest)?;
+        }
+        Ok(())
+    }
+
+    /// Remembers the source of a local mod, which replaces any pinned version
+    fn track_local_mod(&mut self, name: String, source: LocalMod) {
+        self.info.pinned_mods.remove(&name);
+        self.info.local_mods.insert(name, source);
+        self.save();
+    }
+
+    /// Copies local mod zips again, so rebuilt zips are picked up,
+    /// and restores links of mod directories
+    fn sync_local_mods(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for (name, source) in &self.info.local_mods {
+            match source {
+                LocalMod::File(path) if path.exists() => {
+                    let mod_info = self.copy_mod_file(path)?;
+                    if &mod_info.name != name {
+                        return Err(Box::new(InvalidMod(format!(
+                            "{:?}: expected mod {}, found {}",
+                            path, name, mod_info.name
+                        ))));
+                    }
+                },
+                LocalMod::File(path) => log::warn!(
+                    "Zip of mod {} is missing, keeping installed version: {:?}",
+                    name,
+                    path
+                ),
+                LocalMod::Dir(path) => self.link_mod_dir(name, path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs an exact version of a mod and keeps it from being updated
+    pub fn pin_mod(
+        &mut self, name: &str, version: Version,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let downloader = ModDownloader::new()?;
+        let mod_info = downloader.require_exact(name, version)?;
+        self.link_mod(&mod_info);
+        self.info.pinned_mods.insert(name.to_owned(), version);
+        self.save();
+        Ok(())
+    }
+
+    /// Allows a pinned mod to be updated again
+    pub fn unpin_mod(&mut self, name: &str) {
+        if self.info.pinned_mods.remove(name).is_some() {
+            self.save();
+        }
+    }
+
+    pub fn remove_mods(&mut self, mods: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
         let installed_mods = self.mods();
         for remove_mod in mods {
-            for installed_mod in &installed_mods {
-                if installed_mod.name == remove_mod {
-                    self.unlink_mod(installed_mod);
-                    break;
-                }
-                log::warn!("No such mod {:?}", remove_mod);
+            if let Some(LocalMod::Dir(_)) = self.info.local_mods.get(&remove_mod) {
+                self.unlink_mod_dir(&remove_mod)?;
+                self.info.local_mods.remove(&remove_mod);
+                self.save();
+                continue;
+            }
+            if self.info.local_mods.remove(&remove_mod).is_some() {
+                self.save();
+            }
+            match installed_mods.iter().find(|m| m.name == remove_mod) {
+                Some(installed_mod) => self.unlink_mod(installed_mod),
+                None => log::warn!("No such mod {:?}", remove_mod),
             }
+            self.unpin_mod(&remove_mod);
         }
 
         Ok(())
     }
 
+    /// Updates mods from the mod portal, except pinned ones, and syncs local mods
     pub fn update_mods(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.add_mods(self.mods().iter().map(|m| m.name.to_owned()).collect())
+        self.add_mods(
+            self.mods()
+                .iter()
+                .filter(|m| !self.info.pinned_mods.contains_key(&m.name))
+                .filter(|m| !self.info.local_mods.contains_key(&m.name))
+                .map(|m| m.name.to_owned())
+                .collect(),
+        )?;
+        self.sync_local_mods()
+    }
+
+    /// Runs `--benchmark` on a copy of the save with the given Factorio version and mods
+    pub fn benchmark(
+        &self, version: Version, mods: &[ModInfo], ticks: u32,
+    ) -> Result<BenchmarkResult, Box<dyn std::error::Error>> {
+        let s = Self::scratch("benchmark", self.info.config.clone(), version);
+        fs::copy(self.dir.join("world.zip"), s.dir.join("world.zip"))?;
+
+        // Mod settings and lists are kept, mod links are replaced
+        for entry in fs::read_dir(self.dir.join("factorio/mods"))? {
+            let path = entry?.path();
+            if fs::symlink_metadata(&path)?.is_file() {
+                fs::copy(&path, s.dir.join("factorio/mods").join(path.file_name().unwrap()))?;
+            }
+        }
+        for mod_info in mods {
+            s.link_mod(mod_info);
+        }
+        for (mod_name, source) in &self.info.local_mods {
+            if let LocalMod::Dir(path) = source {
+                s.link_mod_dir(mod_name, path)?;
+            }
+        }
+
+        log::info!("Running benchmark with Factorio {} for {} ticks", version, ticks);
+        let output = s
+            .command_base()
+            .args(&["--config", "config.ini", "--mod-directory", "factorio/mods/"])
+            .args(&["--benchmark", "world.zip", "--benchmark-ticks"])
+            .arg(ticks.to_string())
+            .arg("--disable-audio")
+            .output()?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match BenchmarkResult::parse(&stdout) {
+            Some(result) if output.status.success() => Ok(result),
+            _ => Err(Box::new(BenchmarkFailed(stdout.into_owned()))),
+        }
     }
 
     fn command_base(&self) -> Command {
@@ -275,31 +1228,35 @@ impl Server {
     }
 
     /// Generate world based on the settings
-    fn generate_args(&self) -> Vec<&str> {
+    fn generate_args(&self, seed: Option<u32>) -> Vec<String> {
         let mut args = Vec::new();
-        args.push("--config");
-        args.push("config.ini");
-        args.push("--create");
-        args.push("world");
+        args.push("--config".to_owned());
+        args.push("config.ini".to_owned());
+        args.push("--create".to_owned());
+        args.push("world".to_owned());
         if self.dir.join("map-gen-settings.json").exists() {
-            args.push("--map-gen-settings");
-            args.push("map-gen-settings.json");
+            args.push("--map-gen-settings".to_owned());
+            args.push("map-gen-settings.json".to_owned());
         }
         if self.dir.join("map-settings.json").exists() {
-            args.push("--map-settings");
-            args.push("map-settings.json");
+            args.push("--map-settings".to_owned());
+            args.push("map-settings.json".to_owned());
+        }
+        if let Some(seed) = seed {
+            args.push("--map-gen-seed".to_owned());
+            args.push(seed.to_string());
         }
 
         args
     }
 
     /// Generate world based on the settings
-    fn generate(&self) {
+    fn generate(&self, seed: Option<u32>) {
         log::info!("Generating world");
 
         let output = self
             .command_base()
-            .args(self.generate_args())
+            .args(self.generate_args(seed))
             .output()
             .unwrap();
 
@@ -315,12 +1272,29 @@ impl Server {
         let mut args = Vec::new();
         args.push("--config");
         args.push("config.ini");
-        args.push("--start-server");
-        args.push("world.zip");
+        match &self.info.scenario {
+            Some(scenario) if !self.dir.join("world.zip").exists() => {
+                args.push("--start-server-load-scenario");
+                args.push(scenario);
+            },
+            _ => {
+                args.push("--start-server");
+                args.push("world.zip");
+            },
+        }
         args.push("--mod-directory");
         args.push("factorio/mods/");
         args.push("--server-adminlist");
         args.push("server-adminlist.json");
+        if self.dir.join("server-banlist.json").exists() {
+            args.push("--server-banlist");
+            args.push("server-banlist.json");
+        }
+        if self.info.config.use_server_whitelist {
+            args.push("--use-server-whitelist");
+            args.push("--server-whitelist");
+            args.push("server-whitelist.json");
+        }
         if self.dir.join("server-settings.json").exists() {
             args.push("--server-settings");
             args.push("server-settings.json");
@@ -329,6 +1303,18 @@ impl Server {
         args
     }
 
+    fn network_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        args.push("--port".to_owned());
+        args.push(self.port().to_string());
+        if let Some(bind) = self.info.config.bind {
+            args.push("--bind".to_owned());
+            args.push(bind.to_string());
+        }
+
+        args
+    }
+
     fn latest_version(&self) -> Option<ResolvedVersionReq> {
         match self.info.config.factorio.resolve() {
             Ok(latest) => Some(latest),
@@ -352,6 +1338,114 @@ impl Server {
         }
     }
 
+    /// Available update, unless mods are not compatible with it.
+    /// Results of the mod check are remembered in `checked` per version,
+    /// failed checks are tried again on the next call.
+    fn update_candidate(
+        &self, checked: &mut BTreeMap<Version, bool>,
+    ) -> Option<ResolvedVersionReq> {
+        let resolved = self.update_available()?;
+        if let Some(&compatible) = checked.get(&resolved.version) {
+            return if compatible { Some(resolved) } else { None };
+        }
+
+        match self.check_mod_compatibility(resolved.version) {
+            Ok(()) => {
+                checked.insert(resolved.version, true);
+                Some(resolved)
+            },
+            Err(error) if error.is::<IncompatibleMods>() => {
+                log::warn!("{}", error);
+                checked.insert(resolved.version, false);
+                None
+            },
+            Err(error) => {
+                log::warn!(
+                    "Could not check mods for Factorio {}: {}",
+                    resolved.version,
+                    error
+                );
+                None
+            },
+        }
+    }
+
+    /// Makes sure every mod can be used with `version`,
+    /// unless the world allows updating with incompatible mods
+    pub fn check_mod_compatibility(
+        &self, version: Version,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mods = self.incompatible_mods(version)?;
+        if mods.is_empty() {
+            return Ok(());
+        }
+
+        if self.info.config.allow_incompatible_mods {
+            let names: Vec<&str> = mods.iter().map(|(name, _)| name.as_str()).collect();
+            log::warn!(
+                "Updating to Factorio {} with incompatible mods: {}",
+                version,
+                names.join(", ")
+            );
+            return Ok(());
+        }
+
+        Err(Box::new(IncompatibleMods {
+            world: self.name.clone(),
+            version,
+            mods,
+        }))
+    }
+
+    /// Installed mods that can't be used with `version`, and why
+    fn incompatible_mods(
+        &self, version: Version,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+        let portal = ModPortal::new();
+        let mut incompatible = Vec::new();
+
+        let installed = self.mods();
+        for mod_info in &installed {
+            if self.info.local_mods.contains_key(&mod_info.name) {
+                continue;
+            }
+
+            let releases = portal.releases(&mod_info.name)?;
+            if let Some(&pinned) = self.info.pinned_mods.get(&mod_info.name) {
+                let supported = releases
+                    .iter()
+                    .find(|r| Version::try_from_str(&r.version).ok() == Some(pinned))
+                    .map_or(false, |r| r.supports(version));
+                if !supported {
+                    incompatible.push((
+                        mod_info.name.clone(),
+                        format!("pinned version {} does not support it", pinned),
+                    ));
+                }
+            } else if latest_compatible(&releases, version).is_none() {
+                incompatible.push((mod_info.name.clone(), "no compatible release".to_owned()));
+            }
+        }
+
+        for (name, source) in &self.info.local_mods {
+            let path = match source {
+                LocalMod::File(path) if !path.exists() => {
+                    match installed.iter().find(|m| &m.name == name) {
+                        Some(mod_info) => self.installed_mod_path(mod_info),
+                        None => continue,
+                    }
+                },
+                LocalMod::File(path) | LocalMod::Dir(path) => path.clone(),
+            };
+            let result = ModInfoJson::read(&path).and_then(|info| info.validate(&path, version));
+            if let Err(error) = result {
+                incompatible.push((name.clone(), error.to_string()));
+            }
+        }
+
+        Ok(incompatible)
+    }
+
     /// Update server to given version
     pub fn update(
         &mut self, resolved: ResolvedVersionReq,
@@ -367,12 +1461,16 @@ impl Server {
     }
 
     /// Returns Ok(Some) to request update and restart, and Ok(None) to shutdown
-    fn run_once(&self) -> Result<Option<ResolvedVersionReq>, Box<dyn std::error::Error>> {
+    fn run_once(
+        &self, checked: &mut BTreeMap<Version, bool>,
+    ) -> Result<Option<ResolvedVersionReq>, Box<dyn std::error::Error>> {
         log::info!("Starting server {}", self.name);
+        self.adopt_scenario_save()?;
 
         let child = self
             .command_base()
             .args(self.start_args())
+            .args(self.network_args())
             .stdout(Stdio::piped())
             .stdin(Stdio::piped())
             .stderr(Stdio::inherit())
@@ -399,24 +1497,38 @@ impl Server {
         let msg = try_channel!(rx_from.recv());
         assert!(matches!(msg, message::FromServer::StartupComplete));
 
-        log::info!("Server is running");
+        log::info!("Server {} is running", self.name);
+        crate::systemd::notify("READY=1");
+
+        let console = self.console_file();
+        let _ = fs::remove_file(&console);
+        nix::unistd::mkfifo(&console, Mode::S_IRUSR | Mode::S_IWUSR)?;
+        let console_stop = Arc::new(AtomicBool::new(false));
+        let console_handle = {
+            let console = console.clone();
+            let stop = console_stop.clone();
+            let tx = tx_to.clone();
+            thread::spawn(move || console_thread(&console, &stop, tx))
+        };
 
         let mut result = None;
         if self.info.config.autoupdate.live() {
             'outer: loop {
                 let sleep_ms: u64 = 60 * 1000 * self.info.config.autoupdate_interval_minutes;
                 let interval: u64 = 50;
-                for _ in (0..sleep_ms).step_by(interval as usize) {
-                    if crate::SIGINT.load(Ordering::SeqCst) {
+                // At least one step, so that an interval of zero still notices SIGINT
+                for _ in (0..sleep_ms.max(interval)).step_by(interval as usize) {
+                    // Stop waiting if the server exited by itself, e.g. crashed
+                    if crate::SIGINT.load(Ordering::SeqCst) || handle.is_finished() {
                         result = None;
                         break 'outer;
                     }
                     thread::sleep(std::time::Duration::from_millis(interval));
                 }
 
-                if let Some(resolved) = self.update_available() {
+                if let Some(resolved) = self.update_candidate(checked) {
                     if self.info.config.autoupdate == AutoUpdate::Forced {
-                        log::warn!("Autoupdate: restarting server");
+                        log::warn!("Autoupdate: restarting server {}", self.name);
                         tx_to
                             .send(message::ToServer::Shutdown)
                             .expect("Server thread crashed");
@@ -431,7 +1543,7 @@ impl Server {
                         let reply = rx_from.recv().expect("Server thread crashed");
                         if let message::FromServer::State(state) = reply {
                             if state.players_online.is_empty() {
-                                log::warn!("Autoupdate: restarting server");
+                                log::warn!("Autoupdate: restarting server {}", self.name);
                                 tx_to
                                     .send(message::ToServer::Shutdown)
                                     .expect("Server thread crashed");
@@ -448,20 +1560,52 @@ impl Server {
             }
         }
 
-        handle.join().expect("Server thread crashed")?;
+        let server_result = handle.join().expect("Server thread crashed");
+
+        // Wake up the console thread so that it notices the stop flag
+        console_stop.store(true, Ordering::SeqCst);
+        if let Ok(mut pipe) = OpenOptions::new().write(true).open(&console) {
+            let _ = writeln!(pipe);
+        }
+        console_handle.join().expect("Console thread crashed");
+        let _ = fs::remove_file(&console);
+
+        server_result?;
+        self.adopt_scenario_save()?;
         Ok(result)
     }
 
     /// Run the server
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.is_running() {
+            return Err(Box::new(WorldIsRunning(self.name.clone())));
+        }
+
+        for other in Self::worlds_using_port(self.port(), Some(&self.dir)) {
+            if other.is_running() {
+                return Err(Box::new(PortAlreadyAllocated {
+                    port: self.port(),
+                    world: other.name,
+                }));
+            }
+        }
+
+        fs::write(self.pid_file(), std::process::id().to_string())?;
+        let result = self.run_inner();
+        let _ = fs::remove_file(self.pid_file());
+        result
+    }
+
+    fn run_inner(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut checked = BTreeMap::new();
         if self.info.config.autoupdate != AutoUpdate::Disabled {
-            if let Some(resolved) = self.update_available() {
+            if let Some(resolved) = self.update_candidate(&mut checked) {
                 self.update(resolved)?;
             }
             self.update_mods()?;
         }
 
-        while let Some(resolved) = self.run_once()? {
+        while let Some(resolved) = self.run_once(&mut checked)? {
             self.update(resolved)?;
             self.update_mods()?;
         }
@@ -469,3 +1613,73 @@ impl Server {
         Ok(())
     }
 }
+
+/// Runs `build` in the directory of a new world, removing it again if `build` fails
+fn in_new_world<T>(
+    name: &str,
+    build: impl FnOnce(PathBuf) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let dir = crate::dirs::new_world(name)?;
+    let result = build(dir.clone());
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    result
+}
+
+/// Adds the regular files below `dir` to `archive`, as `name` and its subdirectories
+fn append_files<W: Write>(
+    archive: &mut Builder<W>, dir: &Path, name: &Path,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let entry_name = name.join(entry.file_name());
+        if file_type.is_dir() {
+            append_files(archive, &entry.path(), &entry_name)?;
+        } else if file_type.is_file() {
+            archive.append_path_with_name(entry.path(), entry_name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies a directory recursively
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Forwards lines written to the console pipe to the server process
+fn console_thread(path: &Path, stop: &AtomicBool, tx: Sender<message::ToServer>) {
+    // Opening for writing as well keeps the pipe from blocking or reaching EOF without writers
+    let pipe = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .expect("Could not open console pipe");
+
+    for line in BufReader::new(pipe).lines() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let line = line.expect("Could not read console pipe");
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if tx.send(message::ToServer::Command(line)).is_err() {
+            break;
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crossbeam_channel::{bounded, select, Receiver, Sender};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::collections::HashSet;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::process::{Child, ChildStdout};
 use std::str::FromStr;
 use std::thread::{self, JoinHandle};
@@ -90,6 +90,8 @@ pub mod message {
     pub enum ToServer {
         Shutdown,
         GetState,
+        /// Console command or chat message
+        Command(String),
     }
 
     #[derive(Debug, Clone)]
@@ -126,9 +128,11 @@ pub fn run(
     let (tx_stdout, rx_stdout) = bounded::<Option<String>>(0);
     let stdout = child.stdout.take().unwrap();
     let stdout_handle: JoinHandle<()> = thread::spawn(move || stdout_thread(stdout, tx_stdout));
+    let mut stdin = child.stdin.take().unwrap();
 
     let mut state = RunningServer::new();
     let mut startup_complete = false;
+    let mut failure = None;
 
     loop {
         select! {
@@ -144,9 +148,19 @@ pub fn run(
                 message::ToServer::GetState => {
                     tx.send(message::FromServer::State(state.clone())).unwrap();
                 },
+                message::ToServer::Command(command) => {
+                    log::info!("Console: {}", command);
+                    // Fails if Factorio already exited, which stdout reports next
+                    if let Err(error) = writeln!(stdin, "{}", command) {
+                        log::warn!("Could not send console command: {}", error);
+                    }
+                },
             },
             recv(rx_stdout) -> msg => match msg.expect("Recv from stdout") {
-                Some(line) => state.new_line(&line)?,
+                Some(line) => if let Err(error) = state.new_line(&line) {
+                    failure = Some(error);
+                    break;
+                },
                 None => break,
             },
         };
@@ -166,7 +180,12 @@ pub fn run(
     stdout_handle.join().expect("Stdout process crashed");
 
     let exitstatus = child.wait().expect("Server process did not start at all");
-    assert!(exitstatus.success(), "Server process exited unsuccesfully");
+    if let Some(error) = failure {
+        return Err(error);
+    }
+    if !exitstatus.success() {
+        return Err(ServerError::Crashed(exitstatus.to_string()));
+    }
 
     Ok(())
 }
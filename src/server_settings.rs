@@ -0,0 +1,308 @@
+//! Typed representation of Factorio `server-settings.json`
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+use crate::config::Password;
+use crate::error::InvalidServerSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AllowCommands {
+    #[serde(rename = "true")]
+    True,
+    #[serde(rename = "false")]
+    False,
+    #[serde(rename = "admins-only")]
+    AdminsOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Visibility {
+    /// Game will be published on the official Factorio matching server
+    pub public: bool,
+    /// Game will be broadcast on LAN
+    pub lan: bool,
+    /// Keys not known by facts
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+impl Default for Visibility {
+    fn default() -> Self {
+        Self {
+            public: false,
+            lan: true,
+            other: Map::new(),
+        }
+    }
+}
+
+/// Contents of `server-settings.json`, with defaults matching Factorio
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    /// Maximum number of players allowed, admins can join even a full server. 0 means unlimited
+    pub max_players: u32,
+    pub visibility: Visibility,
+    /// Your factorio.com login credentials. Required for games with visibility public
+    pub username: String,
+    pub password: Password,
+    /// Authentication token. May be used instead of password
+    pub token: Password,
+    pub game_password: Password,
+    /// When set to true, the server will only allow clients that have a valid factorio.com account
+    pub require_user_verification: bool,
+    /// 0 means unlimited
+    pub max_upload_in_kilobytes_per_second: u32,
+    /// 0 means unlimited
+    pub max_upload_slots: u32,
+    /// One tick is 16ms in default speed, 0 means no minimum
+    pub minimum_latency_in_ticks: u32,
+    /// Players that played on this map already can join even when the max player limit was reached
+    pub ignore_player_limit_for_returning_players: bool,
+    pub allow_commands: AllowCommands,
+    /// Autosave interval in minutes
+    pub autosave_interval: u32,
+    /// Server autosave slots, it is cycled through when the server autosaves
+    pub autosave_slots: u32,
+    /// How many minutes until someone is kicked when doing nothing, 0 for never
+    pub afk_autokick_interval: u32,
+    /// Whether should the server be paused when no players are present
+    pub auto_pause: bool,
+    pub only_admins_can_pause_the_game: bool,
+    /// Whether autosaves should be saved only on server or also on all connected clients
+    pub autosave_only_on_server: bool,
+    /// Highly experimental feature, enable only at your own risk of losing your saves
+    pub non_blocking_saving: bool,
+    pub minimum_segment_size: u32,
+    pub minimum_segment_size_peer_count: u32,
+    pub maximum_segment_size: u32,
+    pub maximum_segment_size_peer_count: u32,
+    /// Keys not known by facts, preserved as-is
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            description: String::new(),
+            tags: Vec::new(),
+            max_players: 0,
+            visibility: Visibility::default(),
+            username: String::new(),
+            password: Password::default(),
+            token: Password::default(),
+            game_password: Password::default(),
+            require_user_verification: true,
+            max_upload_in_kilobytes_per_second: 0,
+            max_upload_slots: 5,
+            minimum_latency_in_ticks: 0,
+            ignore_player_limit_for_returning_players: false,
+            allow_commands: AllowCommands::AdminsOnly,
+            autosave_interval: 10,
+            autosave_slots: 5,
+            afk_autokick_interval: 0,
+            auto_pause: true,
+            only_admins_can_pause_the_game: true,
+            autosave_only_on_server: true,
+            non_blocking_saving: false,
+            minimum_segment_size: 25,
+            minimum_segment_size_peer_count: 20,
+            maximum_segment_size: 100,
+            maximum_segment_size_peer_count: 10,
+            other: Map::new(),
+        }
+    }
+}
+/// Keys of credentials, which are only shown when asked for by name
+const SECRET_KEYS: &[&str] = &["password", "token", "game_password"];
+
+impl ServerSettings {
+    /// Loads and validates settings from a file
+    pub fn load(path: &Path) -> Result<Self, InvalidServerSettings> {
+        let settings = Self::read(path)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Loads settings from a file without validating them,
+    /// or returns defaults if it doesn't exist.
+    /// Invalid settings of a world can then still be fixed with `set`.
+    pub fn load_or_default(path: &Path) -> Result<Self, InvalidServerSettings> {
+        if path.exists() {
+            Self::read(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, InvalidServerSettings> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| InvalidServerSettings(format!("Could not read {:?}: {}", path, e)))?;
+        serde_json::from_str(&content)
+            .map_err(|e| InvalidServerSettings(format!("{:?}: {}", path, e)))
+    }
+
+    pub fn store(&self, path: &Path) {
+        fs::write(path, serde_json::to_string_pretty(self).unwrap())
+            .expect("Could not write server settings");
+    }
+
+    /// Checks settings that Factorio would reject or misbehave with
+    pub fn validate(&self) -> Result<(), InvalidServerSettings> {
+        if self.visibility.public {
+            if self.name.is_empty() {
+                return Err(InvalidServerSettings(
+                    "Public games require a name, set it with `name`".to_owned(),
+                ));
+            }
+            if self.username.is_empty()
+                || (self.password.plaintext.is_empty() && self.token.plaintext.is_empty())
+            {
+                return Err(InvalidServerSettings(
+                    "Public games require `username` and either `password` or `token`".to_owned(),
+                ));
+            }
+        }
+
+        if self.autosave_interval > 0 && self.autosave_slots == 0 {
+            return Err(InvalidServerSettings(
+                "`autosave_slots` must be positive when autosaves are enabled".to_owned(),
+            ));
+        }
+
+        if self.minimum_segment_size > self.maximum_segment_size {
+            return Err(InvalidServerSettings(
+                "`minimum_segment_size` cannot be larger than `maximum_segment_size`".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// All settings, with credentials that are set replaced by `********`
+    pub fn to_json_masked(&self) -> Value {
+        let mut root = serde_json::to_value(self).unwrap();
+        for key in SECRET_KEYS {
+            match root.get_mut(*key) {
+                Some(value) if value != "" => *value = Value::String("********".to_owned()),
+                _ => {},
+            }
+        }
+        root
+    }
+
+    /// Reads a value by dot-separated key, e.g. `visibility.public`
+    pub fn get(&self, key: &str) -> Result<Value, InvalidServerSettings> {
+        let root = serde_json::to_value(self).unwrap();
+        root.pointer(&json_pointer(key))
+            .cloned()
+            .ok_or_else(|| unknown_key(key))
+    }
+
+    /// Sets a value by dot-separated key, e.g. `visibility.public`.
+    /// The value is interpreted as JSON if possible, and as a string otherwise.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), InvalidServerSettings> {
+        let root = serde_json::to_value(&*self).unwrap();
+        if root.pointer(&json_pointer(key)).is_none() {
+            return Err(unknown_key(key));
+        }
+
+        let mut candidates = Vec::new();
+        if let Ok(parsed) = serde_json::from_str::<Value>(value) {
+            candidates.push(parsed);
+        }
+        candidates.push(Value::String(value.to_owned()));
+
+        let mut error = None;
+        for candidate in candidates {
+            let mut root = root.clone();
+            *root.pointer_mut(&json_pointer(key)).unwrap() = candidate;
+            match serde_json::from_value::<Self>(root) {
+                Ok(settings) => {
+                    settings.validate()?;
+                    *self = settings;
+                    return Ok(());
+                },
+                Err(e) => error = Some(e),
+            }
+        }
+
+        Err(InvalidServerSettings(format!(
+            "Invalid value {:?} for {}: {}",
+            value,
+            key,
+            error.unwrap()
+        )))
+    }
+}
+
+fn json_pointer(key: &str) -> String {
+    format!("/{}", key.replace('.', "/"))
+}
+
+fn unknown_key(key: &str) -> InvalidServerSettings {
+    let known: Vec<String> = match serde_json::to_value(ServerSettings::default()).unwrap() {
+        Value::Object(map) => map.keys().cloned().collect(),
+        _ => unreachable!(),
+    };
+    InvalidServerSettings(format!(
+        "Unknown setting {:?}, known settings are: {}",
+        key,
+        known.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unknown_keys_are_preserved() {
+        let input = r#"{"name": "Test", "future_option": {"a": 1}, "visibility": {"lan": false, "steam": true}}"#;
+        let settings: ServerSettings = serde_json::from_str(input).unwrap();
+        assert_eq!(settings.name, "Test");
+        assert!(!settings.visibility.lan);
+        assert_eq!(settings.autosave_interval, 10);
+
+        let output = serde_json::to_value(&settings).unwrap();
+        assert_eq!(output["future_option"]["a"], 1);
+        assert_eq!(output["visibility"]["steam"], true);
+    }
+
+    #[test]
+    fn secrets_are_masked() {
+        let mut settings = ServerSettings::default();
+        settings.set("game_password", "hunter2").unwrap();
+        let json = settings.to_json_masked();
+        assert_eq!(json["game_password"], "********");
+        assert_eq!(json["token"], "");
+        assert_eq!(settings.get("game_password").unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn set_values() {
+        let mut settings = ServerSettings::default();
+        settings.set("name", "123").unwrap();
+        assert_eq!(settings.name, "123");
+        settings.set("max_players", "8").unwrap();
+        assert_eq!(settings.max_players, 8);
+        settings.set("tags", r#"["a", "b"]"#).unwrap();
+        assert_eq!(settings.tags, vec!["a", "b"]);
+        settings.set("allow_commands", "true").unwrap();
+        assert_eq!(settings.allow_commands, AllowCommands::True);
+        settings.set("visibility.lan", "false").unwrap();
+        assert!(!settings.visibility.lan);
+
+        assert!(settings.set("max_players", "many").is_err());
+        assert!(settings.set("no_such_key", "1").is_err());
+        assert!(settings.set("visibility.public", "true").is_err());
+        assert!(!settings.visibility.public);
+    }
+}
@@ -0,0 +1,137 @@
+//! Global settings of facts in `settings.toml`, shared by all worlds
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::dirs;
+use crate::error::InvalidSettings;
+use crate::http;
+
+lazy_static! {
+    /// Settings of this process, loaded on first use
+    static ref SETTINGS: Result<Settings, InvalidSettings> = Settings::load();
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub urls: Urls,
+    pub http: HttpSettings,
+}
+
+/// Base URLs of the Factorio services, e.g. to use a mirror
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Urls {
+    /// Release list and game downloads
+    pub factorio: String,
+    pub mods: String,
+    /// Login of `facts login`
+    pub auth: String,
+}
+impl Default for Urls {
+    fn default() -> Self {
+        Self {
+            factorio: "https://factorio.com".to_owned(),
+            mods: "https://mods.factorio.com".to_owned(),
+            auth: "https://auth.factorio.com".to_owned(),
+        }
+    }
+}
+impl Urls {
+    pub fn factorio(&self, path: &str) -> String {
+        join(&self.factorio, path)
+    }
+
+    pub fn mods(&self, path: &str) -> String {
+        join(&self.mods, path)
+    }
+
+    pub fn auth(&self, path: &str) -> String {
+        join(&self.auth, path)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    /// Proxy for all requests, e.g. `http://proxy:3128`
+    pub proxy: Option<String>,
+    /// PEM file with an additional trusted certificate
+    pub ca_bundle: Option<PathBuf>,
+    pub user_agent: String,
+    /// Seconds to wait for a connection
+    pub connect_timeout: u64,
+    /// Seconds to wait for a response, or for more data while downloading
+    pub read_timeout: u64,
+}
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            ca_bundle: None,
+            user_agent: format!("facts/{}", env!("CARGO_PKG_VERSION")),
+            connect_timeout: 15,
+            read_timeout: 60,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads `settings.toml`, defaults are used if it doesn't exist
+    pub fn load() -> Result<Self, InvalidSettings> {
+        let path = dirs::settings_file();
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| InvalidSettings(e.to_string())),
+            Err(_) if !path.exists() => Ok(Self::default()),
+            Err(e) => Err(InvalidSettings(e.to_string())),
+        }
+    }
+}
+
+/// Loads the settings and sets up the HTTP client, called once on startup
+/// so that invalid settings are reported before anything else happens
+pub fn init() -> Result<(), InvalidSettings> {
+    SETTINGS.as_ref().map_err(Clone::clone)?;
+    http::init()
+}
+
+/// Settings from `settings.toml`, checked by `init`
+pub fn get() -> &'static Settings {
+    SETTINGS.as_ref().expect("Invalid settings")
+}
+
+pub fn urls() -> &'static Urls {
+    &get().urls
+}
+
+fn join(base: &str, path: &str) -> String {
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [urls]
+            mods = "http://localhost:8080/"
+
+            [http]
+            read_timeout = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.urls.mods("/api/mods"), "http://localhost:8080/api/mods");
+        assert_eq!(settings.urls.factorio("/api"), "https://factorio.com/api");
+        assert_eq!(settings.http.read_timeout, 5);
+        assert_eq!(settings.http.connect_timeout, 15);
+
+        assert!(toml::from_str::<Settings>("[urls]\nmod = \"x\"").is_err());
+    }
+}
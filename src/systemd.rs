@@ -0,0 +1,182 @@
+//! Systemd service unit generation and readiness notifications
+
+use nix::sys::socket::{self, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType, UnixAddr};
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+
+use crate::server::Server;
+
+/// File name of the unit running this server
+pub fn unit_name(server: &Server) -> String {
+    format!(
+        "facts-{}.service",
+        server.dir.file_name().unwrap().to_str().unwrap()
+    )
+}
+
+/// Unit running `facts start` for this server.
+/// Factorio saves the map on SIGINT, so it is used as the stop signal.
+/// Starting may download Factorio and mods first, so it has no time limit.
+pub fn unit(server: &Server, system: bool, log_level: &str) -> String {
+    let exe = env::current_exe().expect("Could not locate facts executable");
+
+    let mut s = String::new();
+    s.push_str("[Unit]\n");
+    s.push_str(&format!(
+        "Description=Factorio server {} (facts)\n",
+        server.name
+    ));
+    s.push_str("After=network-online.target\n");
+    s.push_str("Wants=network-online.target\n");
+    s.push_str("\n[Service]\n");
+    s.push_str("Type=notify\n");
+    s.push_str("NotifyAccess=main\n");
+    s.push_str("TimeoutStartSec=infinity\n");
+    s.push_str(&format!(
+        "ExecStart={} start {}\n",
+        escape(exe.to_str().unwrap()),
+        escape(&server.name)
+    ));
+    s.push_str(&format!(
+        "WorkingDirectory={}\n",
+        server.dir.to_str().unwrap().replace('%', "%%")
+    ));
+    s.push_str(&format!("Environment=FACTS_LOG={}\n", log_level));
+    if system {
+        if let Ok(user) = env::var("USER") {
+            s.push_str(&format!("User={}\n", user));
+        }
+    }
+    s.push_str("KillSignal=SIGINT\n");
+    s.push_str("TimeoutStopSec=120\n");
+    s.push_str("Restart=on-failure\n");
+    s.push_str("RestartSec=10\n");
+    s.push_str("\n[Install]\n");
+    if system {
+        s.push_str("WantedBy=multi-user.target\n");
+    } else {
+        s.push_str("WantedBy=default.target\n");
+    }
+    s
+}
+
+/// Quotes an argument for systemd command lines if required
+fn escape(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    if arg
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./%".contains(c))
+    {
+        arg
+    } else {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn unit_dir_path(system: bool) -> PathBuf {
+    if system {
+        PathBuf::from("/etc/systemd/system")
+    } else if let Some(config) = env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(config).join("systemd/user")
+    } else {
+        PathBuf::from(env::var_os("HOME").expect("HOME not set")).join(".config/systemd/user")
+    }
+}
+
+/// Directory for units, creating it if required
+pub fn unit_dir(system: bool) -> PathBuf {
+    let dir = unit_dir_path(system);
+    fs::create_dir_all(&dir).expect("Could not create dir");
+    dir
+}
+
+/// Replaces installed units of a renamed server, keeping them enabled.
+/// All new units are written before old ones are removed, so that nothing changes
+/// if a unit directory is not writable.
+/// Returns the new unit paths and whether they are system units.
+pub fn rename_units(old: &Server, new: &Server) -> io::Result<Vec<(PathBuf, bool)>> {
+    let old_name = unit_name(old);
+    let new_name = unit_name(new);
+
+    let mut renamed = Vec::new();
+    for &system in &[false, true] {
+        let dir = unit_dir_path(system);
+        let old_path = dir.join(&old_name);
+        if !old_path.exists() {
+            continue;
+        }
+
+        let new_path = dir.join(&new_name);
+        let written = fs::read_to_string(&old_path).and_then(|content| {
+            let log_level = content
+                .lines()
+                .find_map(|l| l.strip_prefix("Environment=FACTS_LOG="))
+                .unwrap_or("info");
+            fs::write(&new_path, unit(new, system, log_level))
+        });
+        if let Err(error) = written {
+            for (path, _) in &renamed {
+                let _ = fs::remove_file(path);
+            }
+            return Err(error);
+        }
+        renamed.push((new_path, system));
+    }
+
+    for (new_path, system) in &renamed {
+        let dir = unit_dir_path(*system);
+        fs::remove_file(dir.join(&old_name))?;
+
+        // `systemctl enable` links units into `<target>.wants/`
+        for entry in fs::read_dir(&dir)? {
+            let wants = entry?.path();
+            if !wants.to_str().map_or(false, |p| p.ends_with(".wants")) {
+                continue;
+            }
+            let link = wants.join(&old_name);
+            if fs::symlink_metadata(&link).is_ok() {
+                fs::remove_file(&link)?;
+                symlink(new_path, wants.join(&new_name))?;
+            }
+        }
+    }
+    Ok(renamed)
+}
+
+/// Sends a state update to systemd, if running under it with `Type=notify`
+pub fn notify(state: &str) {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    let addr = if let Some(name) = path.strip_prefix('@') {
+        UnixAddr::new_abstract(name.as_bytes())
+    } else {
+        UnixAddr::new(path.as_str())
+    };
+
+    let result = addr.and_then(|addr| {
+        let fd = socket::socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let sent = socket::sendto(
+            fd,
+            state.as_bytes(),
+            &SockAddr::Unix(addr),
+            MsgFlags::empty(),
+        );
+        let _ = nix::unistd::close(fd);
+        sent
+    });
+
+    if let Err(error) = result {
+        log::warn!("Could not notify systemd: {}", error);
+    }
+}
@@ -0,0 +1,166 @@
+//! Named templates for creating servers
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::*;
+use crate::dirs;
+use crate::error::InvalidModSettings;
+use crate::mod_settings::ModSettings;
+use crate::modportal::load_mod_list_json;
+use crate::player_lists;
+use crate::server::Server;
+use crate::server_settings::ServerSettings;
+use crate::version::Version;
+
+/// Template info data version format
+const TEMPLATE_INFO_VERSION: u64 = 1;
+
+/// Template data to persist to disk, settings files are stored next to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateInfo {
+    _version: u64,
+    pub meta: MetaConfig,
+    pub admins: Vec<String>,
+    pub mods: Vec<String>,
+    #[serde(default)]
+    pub pinned_mods: BTreeMap<String, Version>,
+}
+
+#[derive(Debug)]
+pub struct Template {
+    pub name: String,
+    pub dir: PathBuf,
+    pub info: TemplateInfo,
+}
+impl Template {
+    /// Loads template by name
+    pub fn get(name: String) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = dirs::get_template(&name)?;
+        let info: TemplateInfo = serde_json::from_slice(&fs::read(dir.join("template.json"))?)?;
+
+        assert_eq!(
+            info._version, TEMPLATE_INFO_VERSION,
+            "Unsupported template info version"
+        );
+
+        Ok(Self { name, dir, info })
+    }
+
+    /// Saves a new template, copying all referenced files
+    pub fn save(
+        name: String, config: CreateConfig, mut mods: Vec<String>,
+        pinned_mods: BTreeMap<String, Version>, force: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = dirs::new_template(&name, force)?;
+
+        if let Some(path) = &config.map_gen_settings {
+            fs::copy(path, dir.join("map-gen-settings.json"))?;
+        }
+        if let Some(path) = &config.map_settings {
+            fs::copy(path, dir.join("map-settings.json"))?;
+        }
+        if let Some(path) = &config.import.server_settings {
+            ServerSettings::load(path)?.store(&dir.join("server-settings.json"));
+        }
+        if let Some(path) = &config.import.mod_settings {
+            // JSON exports lack the types of numbers, which only worlds can provide
+            if path.extension() == Some("json".as_ref()) {
+                return Err(Box::new(InvalidModSettings(format!(
+                    "{:?}: templates need a mod-settings.dat, not a JSON export",
+                    path
+                ))));
+            }
+            ModSettings::load(path)?.store(&dir.join("mod-settings.dat"));
+        }
+
+        let mut admins = match &config.import.server_adminlist {
+            Some(path) => player_lists::load_names(path)?,
+            None => Vec::new(),
+        };
+        player_lists::add_names(&mut admins, &config.import.add_admin);
+
+        if let Some(path) = &config.import.mod_list {
+            mods.extend(load_mod_list_json(path)?);
+        }
+        mods.retain(|m| !pinned_mods.contains_key(m));
+        mods.sort();
+        mods.dedup();
+
+        // Ports are allocated separately for each server
+        let mut meta = config.meta;
+        meta.port = None;
+
+        let info = TemplateInfo {
+            _version: TEMPLATE_INFO_VERSION,
+            meta,
+            admins,
+            mods,
+            pinned_mods,
+        };
+        fs::write(
+            dir.join("template.json"),
+            serde_json::to_string(&info).unwrap(),
+        )
+        .expect("Could not write template info");
+
+        Ok(Self { name, dir, info })
+    }
+
+    /// Config, mods and pinned mods of an existing server, for saving it as a template
+    pub fn capture(server: &Server) -> (CreateConfig, Vec<String>, BTreeMap<String, Version>) {
+        let existing = |file: &str| Some(server.dir.join(file)).filter(|p| p.exists());
+
+        let config = CreateConfig {
+            map_gen_settings: existing("map-gen-settings.json"),
+            map_settings: existing("map-settings.json"),
+            import: ImportConfig {
+                server_settings: existing("server-settings.json"),
+                server_adminlist: existing("server-adminlist.json"),
+                mod_settings: existing("factorio/mods/mod-settings.dat"),
+                ..Default::default()
+            },
+            meta: server.info.config.clone(),
+        };
+
+        let pinned_mods = server.info.pinned_mods.clone();
+        let mods = server
+            .mods()
+            .into_iter()
+            .map(|m| m.name)
+            .filter(|m| !pinned_mods.contains_key(m))
+            .filter(|m| {
+                let local = server.info.local_mods.contains_key(m);
+                if local {
+                    log::warn!("Local mod {} is not included in the template", m);
+                }
+                !local
+            })
+            .collect();
+
+        (config, mods, pinned_mods)
+    }
+
+    /// Server config based on this template
+    pub fn create_config(&self) -> CreateConfig {
+        let existing = |file: &str| Some(self.dir.join(file)).filter(|p| p.exists());
+
+        CreateConfig {
+            map_gen_settings: existing("map-gen-settings.json"),
+            map_settings: existing("map-settings.json"),
+            import: ImportConfig {
+                server_settings: existing("server-settings.json"),
+                add_admin: self.info.admins.clone(),
+                mod_settings: existing("mod-settings.dat"),
+                ..Default::default()
+            },
+            meta: self.info.meta.clone(),
+        }
+    }
+
+    pub fn delete(self) {
+        fs::remove_dir_all(&self.dir).expect("Could not delete dir");
+    }
+}
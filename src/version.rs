@@ -6,8 +6,8 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 use crate::dirs;
-use crate::download::LatestReleases;
-use crate::error::InvalidVersionNumber;
+use crate::download::{LatestReleases, Release};
+use crate::error::{InvalidVersionNumber, NoSuchVersion};
 
 #[derive(Debug, Clone, Copy)]
 pub enum EitherVersion {
@@ -36,6 +36,10 @@ impl EitherVersion {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct Version(u32, u32, u32);
 impl Version {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self(major, minor, patch)
+    }
+
     pub fn try_from_str(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
         lazy_static! {
             static ref RE: Regex =
@@ -53,6 +57,11 @@ impl Version {
         }
     }
 
+    /// Major, minor and patch numbers
+    pub fn parts(self) -> (u32, u32, u32) {
+        (self.0, self.1, self.2)
+    }
+
     pub fn location(self) -> Result<PathBuf, PathBuf> {
         dirs::version_data(self)
     }
@@ -61,6 +70,13 @@ impl Version {
         self == version
     }
 }
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, String> {
+        Self::try_from_str(input).map_err(|e| e.to_string())
+    }
+}
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}.{}.{}", self.0, self.1, self.2)
@@ -146,10 +162,30 @@ impl FromStr for VersionReq {
 impl VersionReq {
     pub fn resolve(&self) -> Result<ResolvedVersionReq, Box<dyn std::error::Error>> {
         Ok(match self {
-            Self::Specific(s) => ResolvedVersionReq {
+            Self::Specific(s) if s.matches('.').count() == 2 => ResolvedVersionReq {
                 version: Version::try_from_str(s)?,
                 stability_hint: None,
             },
+            // Latest release starting with the given segments, e.g. 1.1.x for "1.1"
+            Self::Specific(s) => {
+                let segments = s
+                    .split('.')
+                    .map(str::parse)
+                    .collect::<Result<Vec<u32>, _>>()?;
+                let version = Release::get_all_by_hint(None)?
+                    .into_iter()
+                    .map(|release| release.version)
+                    .filter(|version| {
+                        let (major, minor, patch) = version.parts();
+                        [major, minor, patch].starts_with(&segments)
+                    })
+                    .max()
+                    .ok_or_else(|| NoSuchVersion(s.clone()))?;
+                ResolvedVersionReq {
+                    version,
+                    stability_hint: None,
+                }
+            },
             Self::Stable => ResolvedVersionReq {
                 version: LatestReleases::get()?.stable,
                 stability_hint: Some(true),
@@ -175,54 +211,46 @@ impl fmt::Display for VersionReq {
 mod test {
     use super::*;
 
+    fn v(s: &str) -> Version {
+        Version::try_from_str(s).unwrap()
+    }
+
+    fn v2(s: &str) -> Version2 {
+        Version2::try_from_str(s).unwrap()
+    }
+
+    #[test]
+    fn version2_includes() {
+        let v01 = v2("0.1");
+        assert!(!v01.includes(v("0.0.9")));
+        assert!(v01.includes(v("0.1.0")));
+        assert!(v01.includes(v("0.1.3")));
+        assert!(!v01.includes(v("0.2.0")));
+
+        let v10 = v2("1.0");
+        assert!(!v10.includes(v("0.9.0")));
+        assert!(v10.includes(v("1.0.2")));
+        assert!(!v10.includes(v("1.3.4")));
+        assert!(!v10.includes(v("2.0.6")));
+    }
+
+    #[test]
+    fn either_version_includes() {
+        let exact = EitherVersion::try_from_str("0.18.1").unwrap();
+        assert!(exact.includes(v("0.18.1")));
+        assert!(!exact.includes(v("0.18.2")));
+
+        let group = EitherVersion::try_from_str("0.18").unwrap();
+        assert!(group.includes(v("0.18.2")));
+        assert!(!group.includes(v("0.17.79")));
+    }
+
     #[test]
-    fn version2range_includes() {
-        let v01 = Version2::from_str("0.1");
-        let v02 = Version2::from_str("0.2");
-        let v03 = Version2::from_str("0.3");
-        let v10 = Version2::from_str("1.0");
-        let v20 = Version2::from_str("2.0");
-
-        let vr0 = v01.to(v02);
-        assert!(!vr0.includes(Version::from_str("0.0.9")));
-        assert!(vr0.includes(Version::from_str("0.1.0")));
-        assert!(vr0.includes(Version::from_str("0.1.3")));
-        assert!(vr0.includes(Version::from_str("0.2.0")));
-        assert!(vr0.includes(Version::from_str("0.2.3")));
-        assert!(!vr0.includes(Version::from_str("0.3.0")));
-
-        let vr1 = v10.to(v20);
-        assert!(!vr1.includes(Version::from_str("0.0.9")));
-        assert!(!vr1.includes(Version::from_str("0.1.0")));
-        assert!(!vr1.includes(Version::from_str("0.1.3")));
-        assert!(!vr1.includes(Version::from_str("0.2.0")));
-        assert!(!vr1.includes(Version::from_str("0.2.3")));
-        assert!(!vr1.includes(Version::from_str("0.3.0")));
-        assert!(!vr1.includes(Version::from_str("0.9.0")));
-        assert!(vr1.includes(Version::from_str("1.0.2")));
-        assert!(vr1.includes(Version::from_str("1.3.4")));
-        assert!(vr1.includes(Version::from_str("2.0.6")));
-        assert!(!vr1.includes(Version::from_str("2.3.8")));
-        assert!(!vr1.includes(Version::from_str("3.0.0")));
-
-        let vr2 = v10.to(v10);
-        assert!(!vr2.includes(Version::from_str("0.9.0")));
-        assert!(vr2.includes(Version::from_str("1.0.2")));
-        assert!(!vr2.includes(Version::from_str("1.3.4")));
-        assert!(!vr2.includes(Version::from_str("2.0.6")));
-        assert!(!vr2.includes(Version::from_str("2.3.8")));
-        assert!(!vr2.includes(Version::from_str("3.0.0")));
-
-        let vr3 = v02.to(v03);
-        assert!(!vr3.includes(Version::from_str("0.1.0")));
-        assert!(vr3.includes(Version::from_str("0.2.0")));
-        assert!(vr3.includes(Version::from_str("0.2.5")));
-        assert!(vr3.includes(Version::from_str("0.3.0")));
-        assert!(vr3.includes(Version::from_str("0.3.5")));
-        assert!(!vr3.includes(Version::from_str("1.0.2")));
-        assert!(!vr3.includes(Version::from_str("1.3.4")));
-        assert!(!vr3.includes(Version::from_str("2.0.6")));
-        assert!(!vr3.includes(Version::from_str("2.3.8")));
-        assert!(!vr3.includes(Version::from_str("3.0.0")));
+    fn parse() {
+        assert_eq!(v("1.1.10").to_string(), "1.1.10");
+        assert!(v("1.1.10") > v("1.1.9"));
+        assert!(Version::try_from_str("1.1").is_err());
+        assert!(Version::try_from_str("01.1.0").is_err());
+        assert!(Version2::try_from_str("1.1.0").is_err());
     }
 }
@@ -0,0 +1,529 @@
+//! Test harness: a local HTTP server imitating the Factorio services,
+//! and a facts data directory that points facts at it
+
+#![allow(dead_code)]
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const USERNAME: &str = "engineer";
+pub const PASSWORD: &str = "hunter2";
+const TOKEN: &str = "0123456789abcdef";
+
+/// Factorio stand-in, see the script for how tests control it
+const FAKE_FACTORIO: &str = include_str!("../fake_factorio.sh");
+
+#[derive(Default)]
+struct State {
+    stable: String,
+    experimental: String,
+    /// Releases by mod name, as (version, factorio_version)
+    mods: BTreeMap<String, Vec<(String, String)>>,
+    /// Downloads that fail with a server error before succeeding
+    failing_downloads: usize,
+    /// Downloads that are cut off halfway
+    interrupted_downloads: usize,
+    /// Request log, as "METHOD path" with " range=..." for range requests
+    requests: Vec<String>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    headers: Vec<String>,
+    body: Vec<u8>,
+    /// Closes the connection after this many bytes of the body
+    cut_off: Option<usize>,
+}
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body: body.into(),
+            cut_off: None,
+        }
+    }
+
+    fn json(value: serde_json::Value) -> Self {
+        Self::new(200, "application/json", value.to_string())
+    }
+
+    fn not_found() -> Self {
+        Self::new(404, "application/json", r#"{"message":"Not found"}"#)
+    }
+}
+
+/// In-process imitation of factorio.com, the mod portal and the auth API
+pub struct MockServices {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+impl MockServices {
+    pub fn start(stable: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State {
+            stable: stable.to_owned(),
+            experimental: stable.to_owned(),
+            ..State::default()
+        }));
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let state = shared.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream.unwrap(), &state);
+                });
+            }
+        });
+
+        Self { url, state }
+    }
+
+    pub fn set_stable(&self, version: &str) {
+        self.state.lock().unwrap().stable = version.to_owned();
+    }
+
+    pub fn set_experimental(&self, version: &str) {
+        self.state.lock().unwrap().experimental = version.to_owned();
+    }
+
+    /// Publishes a mod release compatible with `factorio_version`, e.g. "1.1"
+    pub fn add_mod(&self, name: &str, version: &str, factorio_version: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .mods
+            .entry(name.to_owned())
+            .or_default()
+            .push((version.to_owned(), factorio_version.to_owned()));
+    }
+
+    pub fn fail_downloads(&self, count: usize) {
+        self.state.lock().unwrap().failing_downloads = count;
+    }
+
+    pub fn interrupt_downloads(&self, count: usize) {
+        self.state.lock().unwrap().interrupted_downloads = count;
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+fn handle(stream: TcpStream, state: &Mutex<State>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let target = parts.next().unwrap_or_default().to_owned();
+
+    let mut content_length = 0;
+    let mut range = None;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (key, value) = header.split_once(':').unwrap();
+        match key.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse().unwrap(),
+            "range" => range = Some(value.trim().to_owned()),
+            _ => {},
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let mut response = route(state, &method, path, &params(query), &params(&body_str(&body)));
+
+    let mut request = format!("{} {}", method, path);
+    if let Some(range) = &range {
+        request.push_str(&format!(" range={}", range));
+    }
+    state.lock().unwrap().requests.push(request);
+
+    if response.status == 200 {
+        if let Some(start) = range.as_deref().and_then(range_start) {
+            let total = response.body.len();
+            response.body.drain(..start.min(total));
+            response.status = 206;
+            response
+                .headers
+                .push(format!("Content-Range: bytes {}-{}/{}", start, total - 1, total));
+        }
+    }
+    write_response(stream, response)
+}
+
+fn route(
+    state: &Mutex<State>, method: &str, path: &str, query: &BTreeMap<String, String>,
+    form: &BTreeMap<String, String>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["api", "latest-releases"]) => Response::json(json!({
+            "stable": { "headless": state.stable },
+            "experimental": { "headless": state.experimental },
+        })),
+        ("GET", ["download-headless"]) => release_page(&state.stable, "stable"),
+        ("GET", ["download-headless", "experimental"]) => {
+            release_page(&state.experimental, "experimental")
+        },
+        ("GET", ["get-download", version, "headless", "linux64"]) => {
+            if let Some(response) = download_failure(&mut state) {
+                return response;
+            }
+            let mut response = Response::new(200, "application/octet-stream", factorio_archive());
+            response.headers.push(format!(
+                "Content-Disposition: attachment; filename=factorio_headless_x64_{}.tar.xz",
+                version
+            ));
+            interrupt(&mut state, response)
+        },
+        ("POST", ["api-login"]) => {
+            let valid = form.get("username").map(String::as_str) == Some(USERNAME)
+                && form.get("password").map(String::as_str) == Some(PASSWORD);
+            if valid {
+                Response::json(json!({ "username": USERNAME, "token": TOKEN }))
+            } else {
+                let mut response = Response::json(json!({
+                    "error": "login-failed",
+                    "message": "Invalid username or password"
+                }));
+                response.status = 401;
+                response
+            }
+        },
+        ("GET", ["api", "mods", name]) | ("GET", ["api", "mods", name, "full"]) => {
+            match state.mods.get(*name) {
+                Some(releases) => Response::json(json!({
+                    "name": name,
+                    "title": name,
+                    "releases": releases.iter().map(|(version, factorio_version)| json!({
+                        "download_url": format!("/download/{}/{}", name, version),
+                        "version": version,
+                        "info_json": { "factorio_version": factorio_version },
+                    })).collect::<Vec<_>>(),
+                })),
+                None => Response::not_found(),
+            }
+        },
+        ("GET", ["download", name, version]) => {
+            let authorized = query.get("username").map(String::as_str) == Some(USERNAME)
+                && query.get("token").map(String::as_str) == Some(TOKEN);
+            if !authorized {
+                // The portal redirects to its login page
+                return Response::new(200, "text/html", "<html>Log in</html>");
+            }
+            if let Some(response) = download_failure(&mut state) {
+                return response;
+            }
+            let response = Response::new(200, "application/zip", mod_zip(name, version));
+            interrupt(&mut state, response)
+        },
+        _ => Response::not_found(),
+    }
+}
+
+fn release_page(version: &str, stability: &str) -> Response {
+    Response::new(
+        200,
+        "text/html",
+        format!("<html><body><h3>{} ({})</h3></body></html>", version, stability),
+    )
+}
+
+fn download_failure(state: &mut State) -> Option<Response> {
+    if state.failing_downloads > 0 {
+        state.failing_downloads -= 1;
+        Some(Response::new(503, "text/plain", "Service unavailable"))
+    } else {
+        None
+    }
+}
+
+fn interrupt(state: &mut State, mut response: Response) -> Response {
+    if state.interrupted_downloads > 0 {
+        state.interrupted_downloads -= 1;
+        response.cut_off = Some(response.body.len() / 2);
+    }
+    response
+}
+
+fn write_response(mut stream: TcpStream, response: Response) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    for header in &response.headers {
+        head.push_str(header);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    let end = response.cut_off.unwrap_or(response.body.len());
+    stream.write_all(&response.body[..end])?;
+    stream.flush()
+}
+
+fn range_start(range: &str) -> Option<usize> {
+    range.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()
+}
+
+fn body_str(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).into_owned()
+}
+
+fn params(s: &str) -> BTreeMap<String, String> {
+    s.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
+
+/// Headless archive with the fake Factorio executable
+fn factorio_archive() -> Vec<u8> {
+    let mut builder = tar::Builder::new(xz2::write::XzEncoder::new(Vec::new(), 1));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(FAKE_FACTORIO.len() as u64);
+    header.set_mode(0o755);
+    builder
+        .append_data(&mut header, "factorio/bin/x64/factorio", FAKE_FACTORIO.as_bytes())
+        .unwrap();
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+pub fn mod_zip(name: &str, version: &str) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file(
+        format!("{}_{}/info.json", name, version),
+        zip::write::FileOptions::default(),
+    )
+    .unwrap();
+    let info = json!({ "name": name, "version": version, "factorio_version": "1.1" });
+    zip.write_all(info.to_string().as_bytes()).unwrap();
+    zip.finish().unwrap().into_inner()
+}
+
+/// Temporary facts data directory using the mock services
+pub struct Facts {
+    /// Used as `XDG_DATA_HOME`
+    root: PathBuf,
+    pub services: MockServices,
+}
+impl Facts {
+    pub fn new(stable: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "facts-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+
+        let services = MockServices::start(stable);
+        let facts = Self { root, services };
+        fs::create_dir_all(facts.app_root()).unwrap();
+        fs::write(
+            facts.app_root().join("settings.toml"),
+            format!(
+                "[urls]\nfactorio = {url:?}\nmods = {url:?}\nauth = {url:?}\n",
+                url = facts.services.url
+            ),
+        )
+        .unwrap();
+        facts
+    }
+
+    /// Directory of all facts data
+    pub fn app_root(&self) -> PathBuf {
+        self.root.join("facts")
+    }
+
+    pub fn world(&self, name: &str) -> PathBuf {
+        self.app_root().join("worlds").join(name)
+    }
+
+    pub fn version_dir(&self, version: &str) -> PathBuf {
+        self.app_root().join("versions").join(version)
+    }
+
+    /// Factorio version of a world, from its `facts.json`
+    pub fn current_version(&self, world: &str) -> String {
+        let info: serde_json::Value =
+            serde_json::from_slice(&fs::read(self.world(world).join("facts.json")).unwrap())
+                .unwrap();
+        let parts: Vec<String> = info["current_version"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        parts.join(".")
+    }
+
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_facts"));
+        cmd.args(args)
+            .env("XDG_DATA_HOME", &self.root)
+            .env("FACTS_LOG", "info")
+            .env_remove("HTTP_PROXY")
+            .env_remove("http_proxy")
+            .env_remove("HTTPS_PROXY")
+            .env_remove("https_proxy")
+            .env_remove("ALL_PROXY")
+            .env_remove("all_proxy");
+        cmd
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// Runs facts and returns its log output, panicking if it fails
+    pub fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        let log = log_of(&output);
+        assert!(output.status.success(), "facts {:?} failed:\n{}", args, log);
+        log
+    }
+
+    /// Runs facts and returns its log output, panicking if it succeeds
+    pub fn fails(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        let log = log_of(&output);
+        assert!(!output.status.success(), "facts {:?} succeeded:\n{}", args, log);
+        log
+    }
+
+    pub fn login(&self) {
+        self.ok(&["login", USERNAME, PASSWORD]);
+    }
+
+    /// Starts a long running command, like `start`, in its own process group
+    pub fn spawn(&self, mut cmd: Command) -> Running {
+        let mut child = cmd
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let log = Arc::new(Mutex::new(String::new()));
+        let readers = vec![
+            collect(child.stdout.take().unwrap(), log.clone()),
+            collect(child.stderr.take().unwrap(), log.clone()),
+        ];
+        Running {
+            child,
+            log,
+            readers,
+        }
+    }
+}
+impl Drop for Facts {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Time to wait for expected output before failing
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// facts running in the background, together with Factorio started by it
+pub struct Running {
+    child: Child,
+    log: Arc<Mutex<String>>,
+    readers: Vec<thread::JoinHandle<()>>,
+}
+impl Running {
+    pub fn log(&self) -> String {
+        self.log.lock().unwrap().clone()
+    }
+
+    /// Waits until `text` has been logged `count` times
+    pub fn wait_for(&self, text: &str, count: usize) {
+        let start = Instant::now();
+        while self.log().matches(text).count() < count {
+            assert!(start.elapsed() < TIMEOUT, "Timed out waiting for {:?}:\n{}", text, self.log());
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Sends SIGINT to facts and Factorio, like Ctrl-C in a terminal
+    pub fn interrupt(&self) {
+        let group = nix::unistd::Pid::from_raw(self.child.id() as i32);
+        nix::sys::signal::killpg(group, nix::sys::signal::Signal::SIGINT).unwrap();
+    }
+
+    /// Waits for facts to exit, returning its status and log output
+    pub fn wait(mut self) -> (ExitStatus, String) {
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                break status;
+            }
+            assert!(start.elapsed() < TIMEOUT, "Timed out waiting for exit:\n{}", self.log());
+            thread::sleep(Duration::from_millis(50));
+        };
+        for reader in self.readers.drain(..) {
+            reader.join().unwrap();
+        }
+        (status, self.log())
+    }
+}
+impl Drop for Running {
+    fn drop(&mut self) {
+        // Don't leave processes behind when a test fails
+        let group = nix::unistd::Pid::from_raw(self.child.id() as i32);
+        let _ = nix::sys::signal::killpg(group, nix::sys::signal::Signal::SIGKILL);
+        let _ = self.child.wait();
+    }
+}
+
+fn collect(stream: impl Read + Send + 'static, log: Arc<Mutex<String>>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            let mut log = log.lock().unwrap();
+            log.push_str(&line);
+            log.push('\n');
+        }
+    })
+}
+
+fn log_of(output: &Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+pub fn is_link(path: &Path) -> bool {
+    fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink())
+}
@@ -0,0 +1,96 @@
+#!/bin/sh
+# Stand-in for the Factorio headless server, writing the log lines facts reacts to.
+# Behaviour is scripted with environment variables:
+#   FAKE_FACTORIO_PLAYERS     players that join after startup, separated by spaces
+#   FAKE_FACTORIO_FAIL        "port" reports the UDP port as taken, "crash" exits after startup
+#   FAKE_FACTORIO_SAVE_DELAY  seconds spent saving the map on shutdown
+# Console commands are read from stdin, SIGINT saves and quits like the real server.
+
+START=$(date +%s)
+STATE=Ready
+
+info() {
+    printf '%4d.%03d %s\n' $(($(date +%s) - START)) 0 "$*"
+}
+
+event() {
+    printf '%s [%s] %s\n' "$(date '+%Y-%m-%d %H:%M:%S')" "$1" "$2"
+}
+
+change_state() {
+    info "Info ServerMultiplayerManager.cpp:894: updateTick(0) changing state from($STATE) to($1)"
+    STATE=$1
+}
+
+shutdown() {
+    trap '' INT
+    info "Info ServerMultiplayerManager.cpp:111: Quitting multiplayer connection."
+    change_state DisconnectingScheduled
+    change_state Disconnecting
+    change_state Disconnected
+    info "Info AppManager.cpp:286: Saving map as $WORLD"
+    sleep "${FAKE_FACTORIO_SAVE_DELAY:-0}"
+    info "Info AppManagerStates.cpp:2001: Saving finished"
+    change_state Closed
+    info "Goodbye"
+    exit 0
+}
+
+WORLD=world.zip
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --create)
+            echo world > "$2.zip"
+            exit 0
+            ;;
+        --start-server)
+            WORLD=$2
+            ;;
+    esac
+    shift
+done
+
+info "Info Main.cpp:772: Factorio 1.1.0 (build 0, linux64, headless)"
+trap shutdown INT
+change_state PreparedToHostGame
+
+if [ "$FAKE_FACTORIO_FAIL" = port ]; then
+    info "Error ServerMultiplayerManager.cpp:85: MultiplayerManager failed: Host address is already in use."
+    change_state Failed
+    info "Goodbye"
+    exit 1
+fi
+
+change_state CreatingGame
+change_state InGame
+
+for player in $FAKE_FACTORIO_PLAYERS; do
+    event JOIN "$player joined the game"
+done
+
+if [ "$FAKE_FACTORIO_FAIL" = crash ]; then
+    info "Received SIGSEGV"
+    exit 139
+fi
+
+# `read` returns early when SIGINT arrives, the trap then shuts down
+while true; do
+    if read -r command; then
+        case "$command" in
+            "/ban "*)
+                player=$(echo "$command" | cut -d ' ' -f 2)
+                event BAN "$player was banned by <server>."
+                event LEAVE "$player left the game"
+                ;;
+            /quit)
+                shutdown
+                ;;
+            *)
+                event CHAT "<server>: $command"
+                ;;
+        esac
+    elif [ "$STATE" = InGame ]; then
+        # Stdin was closed, facts is gone
+        exit 1
+    fi
+done
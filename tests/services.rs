@@ -0,0 +1,231 @@
+//! Downloads, login and mod resolution against the mock Factorio services
+
+mod common;
+
+use common::{is_link, Facts, PASSWORD, USERNAME};
+
+#[test]
+fn login() {
+    let facts = Facts::new("1.1.0");
+
+    let log = facts.fails(&["login", USERNAME, "wrong"]);
+    assert!(log.contains("Login failed: Invalid username or password"), "{}", log);
+    assert!(!facts.app_root().join("credentials.json").exists());
+
+    facts.ok(&["login", USERNAME, PASSWORD]);
+    let credentials = std::fs::read_to_string(facts.app_root().join("credentials.json")).unwrap();
+    assert!(credentials.contains(USERNAME));
+}
+
+#[test]
+fn create_downloads_factorio() {
+    let facts = Facts::new("1.1.0");
+    facts.ok(&["create", "A"]);
+
+    assert!(facts.version_dir("1.1.0").join("factorio/bin/x64/factorio").exists());
+    assert!(facts.world("A").join("world.zip").exists());
+    assert_eq!(facts.current_version("A"), "1.1.0");
+    assert!(!facts.app_root().join("downloads/factorio-headless_1.1.0.tar.xz").exists());
+
+    // Already downloaded versions are reused
+    facts.ok(&["create", "B"]);
+    let downloads = facts
+        .services
+        .requests()
+        .iter()
+        .filter(|r| r.starts_with("GET /get-download/"))
+        .count();
+    assert_eq!(downloads, 1);
+}
+
+#[test]
+fn create_specific_version() {
+    let facts = Facts::new("1.1.0");
+    facts.ok(&["create", "A", "--factorio", "1.1.0"]);
+    assert_eq!(facts.current_version("A"), "1.1.0");
+    facts.ok(&["create", "C", "--factorio", "1.1"]);
+    assert_eq!(facts.current_version("C"), "1.1.0");
+    let log = facts.fails(&["create", "D", "--factorio", "0.17"]);
+    assert!(log.contains("No Factorio release matches version 0.17"), "{}", log);
+
+    let log = facts.fails(&["create", "B", "--factorio", "0.17.79"]);
+    assert!(log.contains("0.17.79"), "{}", log);
+    assert!(!facts.world("B").exists());
+    facts.ok(&["list"]);
+}
+
+#[test]
+fn add_mod_requires_login() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.0.0", "1.1");
+    facts.ok(&["create", "A"]);
+
+    let log = facts.fails(&["add-mod", "A", "foo"]);
+    assert!(log.contains("Log in first"), "{}", log);
+}
+
+#[test]
+fn add_mod_downloads_latest_compatible() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.0.0", "1.0");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.services.add_mod("foo", "2.0.0", "2.0");
+    facts.services.add_mod("bar", "0.3.1", "1.1");
+    facts.ok(&["create", "A"]);
+    facts.login();
+
+    facts.ok(&["add-mod", "A", "foo", "bar"]);
+    let mods = facts.world("A").join("factorio/mods");
+    assert!(is_link(&mods.join("foo_1.1.0.zip")));
+    assert!(is_link(&mods.join("bar_0.3.1.zip")));
+    assert!(facts.app_root().join("mods/foo_1.1.0.zip").exists());
+
+    let log = facts.fails(&["add-mod", "A", "missing"]);
+    assert!(log.contains("missing"), "{}", log);
+}
+
+#[test]
+fn downloads_are_retried_and_resumed() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.services.fail_downloads(1);
+    facts.services.interrupt_downloads(1);
+    facts.ok(&["create", "A"]);
+    assert!(facts.version_dir("1.1.0").join("factorio/bin/x64/factorio").exists());
+
+    facts.login();
+    facts.services.interrupt_downloads(1);
+    facts.ok(&["add-mod", "A", "foo"]);
+    let zip = std::fs::read(facts.app_root().join("mods/foo_1.1.0.zip")).unwrap();
+    assert!(zip.starts_with(b"PK"));
+
+    let resumed = facts
+        .services
+        .requests()
+        .iter()
+        .filter(|r| r.contains(" range=bytes="))
+        .count();
+    assert_eq!(resumed, 2);
+}
+
+#[test]
+fn update_and_prune() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.services.add_mod("foo", "1.1.1", "1.1");
+    facts.ok(&["create", "A"]);
+    facts.login();
+    facts.ok(&["add-mod", "A", "foo"]);
+
+    facts.services.set_stable("1.1.1");
+    facts.ok(&["update", "A"]);
+    assert_eq!(facts.current_version("A"), "1.1.1");
+    assert!(is_link(&facts.world("A").join("factorio/mods/foo_1.1.1.zip")));
+
+    facts.ok(&["prune"]);
+    assert!(!facts.version_dir("1.1.0").exists());
+    assert!(facts.version_dir("1.1.1").exists());
+}
+
+#[test]
+fn update_waits_for_compatible_mods() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.ok(&["create", "A"]);
+    facts.login();
+    facts.ok(&["add-mod", "A", "foo"]);
+
+    facts.services.set_stable("2.0.0");
+    let log = facts.fails(&["update", "A"]);
+    assert!(log.contains("foo"), "{}", log);
+    assert_eq!(facts.current_version("A"), "1.1.0");
+
+    facts.services.add_mod("foo", "2.0.0", "2.0");
+    facts.ok(&["update", "A"]);
+    assert_eq!(facts.current_version("A"), "2.0.0");
+    assert!(is_link(&facts.world("A").join("factorio/mods/foo_2.0.0.zip")));
+}
+
+#[test]
+fn local_mod_zips_stay_in_world() {
+    let facts = Facts::new("1.1.0");
+    facts.ok(&["create", "A"]);
+
+    let zip = facts.app_root().join("foo_1.1.0.zip");
+    std::fs::write(&zip, common::mod_zip("foo", "1.1.0")).unwrap();
+    facts.ok(&["add-mod", "A", "--file", zip.to_str().unwrap()]);
+
+    let installed = facts.world("A").join("factorio/mods/foo_1.1.0.zip");
+    assert!(installed.is_file() && !is_link(&installed));
+    assert!(!facts.app_root().join("mods/foo_1.1.0.zip").exists());
+
+    facts.services.add_mod("foo", "1.1.1", "1.1");
+    facts.login();
+    let log = facts.ok(&["add-mod", "A", "foo"]);
+    assert!(log.contains("Mod foo is installed locally"), "{}", log);
+    assert!(installed.is_file() && !is_link(&installed));
+
+    facts.ok(&["remove-mod", "A", "foo"]);
+    assert!(!installed.exists());
+}
+
+#[test]
+fn bundles_keep_mods_in_world() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.ok(&["create", "A"]);
+    facts.login();
+    facts.ok(&["add-mod", "A", "foo"]);
+
+    let bundle = facts.app_root().join("A.tar.xz");
+    let bundle = bundle.to_str().unwrap();
+    facts.ok(&["export", "A", bundle, "--bundle", "--include-mods"]);
+    let shared = facts.app_root().join("mods/foo_1.1.0.zip");
+    std::fs::remove_file(&shared).unwrap();
+
+    facts.ok(&["import", "B", bundle, "--bundle", "--autostart", "true"]);
+    let installed = facts.world("B").join("factorio/mods/foo_1.1.0.zip");
+    assert!(installed.is_file() && !is_link(&installed));
+    assert!(!shared.exists());
+    let info = std::fs::read_to_string(facts.world("B").join("facts.json")).unwrap();
+    assert!(info.contains("\"autostart\":true"), "{}", info);
+}
+
+#[test]
+fn bundles_with_links_are_rejected() {
+    let facts = Facts::new("1.1.0");
+    let bundle = facts.app_root().join("evil.tar.xz");
+    let mut archive = tar::Builder::new(xz2::write::XzEncoder::new(
+        std::fs::File::create(&bundle).unwrap(),
+        6,
+    ));
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_link_name("/tmp/elsewhere").unwrap();
+    archive
+        .append_data(&mut header, "world.zip", std::io::empty())
+        .unwrap();
+    archive.into_inner().unwrap().finish().unwrap();
+
+    let log = facts.fails(&["import", "A", bundle.to_str().unwrap(), "--bundle"]);
+    assert!(log.contains("not a regular file"), "{}", log);
+    assert!(!facts.world("A").exists());
+}
+
+#[test]
+fn add_mod_keeps_pinned_versions() {
+    let facts = Facts::new("1.1.0");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.services.add_mod("foo", "1.1.1", "1.1");
+    facts.login();
+    let manifest = facts.app_root().join("servers.toml");
+    std::fs::write(&manifest, "[worlds.A.mods]\nfoo = \"1.1.0\"\n").unwrap();
+    facts.ok(&["apply", manifest.to_str().unwrap()]);
+
+    let log = facts.ok(&["add-mod", "A", "foo"]);
+    assert!(log.contains("Mod foo is pinned to 1.1.0"), "{}", log);
+    let mods = facts.world("A").join("factorio/mods");
+    assert!(is_link(&mods.join("foo_1.1.0.zip")));
+    assert!(!mods.join("foo_1.1.1.zip").exists());
+}
@@ -0,0 +1,164 @@
+//! Running servers with the fake Factorio from `fake_factorio.sh`
+
+mod common;
+
+use common::Facts;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RUNNING: &str = "Server A is running";
+
+/// World A on Factorio 1.1.0, checking for updates continuously with `autoupdate`
+fn world(autoupdate: &str) -> Facts {
+    let facts = Facts::new("1.1.0");
+    facts.ok(&[
+        "create",
+        "A",
+        "--autoupdate",
+        autoupdate,
+        "--autoupdate-interval-minutes",
+        "0",
+    ]);
+    facts
+}
+
+#[test]
+fn start_and_stop() {
+    for autoupdate in &["enabled", "disabled"] {
+        let facts = world(autoupdate);
+        let running = facts.spawn(facts.command(&["start", "A"]));
+        running.wait_for(RUNNING, 1);
+        assert!(facts.world("A").join("console.fifo").exists());
+
+        running.interrupt();
+        let (status, log) = running.wait();
+        assert!(status.success(), "{}", log);
+        assert!(!facts.world("A").join("console.fifo").exists());
+        assert!(!facts.world("A").join("facts.pid").exists());
+    }
+}
+
+#[test]
+fn port_in_use() {
+    let facts = world("disabled");
+    let mut cmd = facts.command(&["start", "A"]);
+    cmd.env("FAKE_FACTORIO_FAIL", "port");
+    let (status, log) = facts.spawn(cmd).wait();
+    assert!(!status.success());
+    assert!(log.contains("UDP port already in use"), "{}", log);
+    assert!(!log.contains(RUNNING), "{}", log);
+}
+
+#[test]
+fn crash_is_reported() {
+    for autoupdate in &["enabled", "disabled"] {
+        let facts = world(autoupdate);
+        let mut cmd = facts.command(&["start", "A"]);
+        cmd.env("FAKE_FACTORIO_FAIL", "crash");
+        let (status, log) = facts.spawn(cmd).wait();
+        assert_eq!(status.code(), Some(1), "{}", log);
+        assert!(log.contains("Server process crashed (exit status: 139)"), "{}", log);
+    }
+}
+
+#[test]
+fn autoupdate_without_players() {
+    let facts = world("enabled");
+    let running = facts.spawn(facts.command(&["start", "A"]));
+    running.wait_for(RUNNING, 1);
+
+    facts.services.set_stable("1.1.1");
+    running.wait_for(RUNNING, 2);
+    assert_eq!(facts.current_version("A"), "1.1.1");
+
+    running.interrupt();
+    let (status, log) = running.wait();
+    assert!(status.success(), "{}", log);
+    assert!(log.contains("Autoupdate: restarting server A"), "{}", log);
+}
+
+#[test]
+fn autoupdate_waits_for_players() {
+    let facts = world("enabled");
+    let mut cmd = facts.command(&["start", "A"]);
+    cmd.env("FAKE_FACTORIO_PLAYERS", "alice");
+    let running = facts.spawn(cmd);
+    running.wait_for("[JOIN] alice joined the game", 1);
+
+    facts.services.set_stable("1.1.1");
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(facts.current_version("A"), "1.1.0");
+
+    // The ban goes through the console to the server, which kicks the player
+    facts.ok(&["bans", "A", "add", "alice"]);
+    running.wait_for("[LEAVE] alice left the game", 1);
+    running.wait_for(RUNNING, 2);
+    assert_eq!(facts.current_version("A"), "1.1.1");
+
+    running.interrupt();
+    let (status, log) = running.wait();
+    assert!(status.success(), "{}", log);
+}
+
+#[test]
+fn forced_autoupdate_ignores_players() {
+    let facts = world("forced");
+    let mut cmd = facts.command(&["start", "A"]);
+    cmd.env("FAKE_FACTORIO_PLAYERS", "alice");
+    let running = facts.spawn(cmd);
+    running.wait_for("[JOIN] alice joined the game", 1);
+
+    facts.services.set_stable("1.1.1");
+    running.wait_for(RUNNING, 2);
+    assert_eq!(facts.current_version("A"), "1.1.1");
+
+    running.interrupt();
+    let (status, log) = running.wait();
+    assert!(status.success(), "{}", log);
+}
+
+#[test]
+fn autoupdate_checks_mods_once_per_version() {
+    let facts = world("enabled");
+    facts.services.add_mod("foo", "1.1.0", "1.1");
+    facts.login();
+    facts.ok(&["add-mod", "A", "foo"]);
+    let running = facts.spawn(facts.command(&["start", "A"]));
+    running.wait_for(RUNNING, 1);
+
+    let count = |prefix: &str| {
+        facts.services.requests().iter().filter(|r| r.starts_with(prefix)).count()
+    };
+    let mod_requests = count("GET /api/mods/foo");
+    facts.services.set_stable("2.0.0");
+    running.wait_for("Not updating A to Factorio 2.0.0", 1);
+    let checks = count("GET /api/latest-releases") + 5;
+    let start = Instant::now();
+    while count("GET /api/latest-releases") < checks {
+        assert!(start.elapsed() < Duration::from_secs(10), "{}", running.log());
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(count("GET /api/mods/foo"), mod_requests + 1);
+    assert_eq!(running.log().matches("Not updating A").count(), 1);
+    assert_eq!(facts.current_version("A"), "1.1.0");
+
+    running.interrupt();
+    let (status, log) = running.wait();
+    assert!(status.success(), "{}", log);
+}
+
+#[test]
+fn second_sigint_abandons_shutdown() {
+    let facts = world("enabled");
+    let mut cmd = facts.command(&["start", "A"]);
+    cmd.env("FAKE_FACTORIO_SAVE_DELAY", "5");
+    let running = facts.spawn(cmd);
+    running.wait_for(RUNNING, 1);
+
+    running.interrupt();
+    running.wait_for("Received SIGINT", 1);
+    running.interrupt();
+    let (status, log) = running.wait();
+    assert_eq!(status.code(), Some(2), "{}", log);
+    assert!(log.contains("Second SIGINT, abandoning clean up"), "{}", log);
+}